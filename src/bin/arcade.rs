@@ -0,0 +1,161 @@
+//! Play the day 13 breakout game in the terminal.
+//!
+//! ```text
+//! cargo run --bin arcade [input/day13]
+//! ```
+//!
+//! Steer with the arrow keys or `a` and `d`, hold the paddle still with
+//! space or `s`, and quit with `q` or Ctrl-C. The game only moves on when
+//! given a joystick move, so there is no hurry.
+use aoc_2019::day13::Arcade;
+use aoc_2019::int_code::Program;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Joystick(i64),
+    Quit,
+}
+/// Read bytes until one makes a known key, or None at the end of input.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let mut byte = [0];
+    let mut escape = 0;
+    loop {
+        if input.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        // arrows come as ESC [ A to ESC [ D, and a byte that breaks off the
+        // sequence still counts as a key of its own
+        let key = match (escape, byte[0]) {
+            (_, 0x1b) => {
+                escape = 1;
+                continue;
+            }
+            (1, b'[') => {
+                escape = 2;
+                continue;
+            }
+            (2, b'D') | (_, b'a') => Key::Joystick(-1),
+            (2, b'C') | (_, b'd') => Key::Joystick(1),
+            (2, b'B') | (_, b's') | (_, b' ') => Key::Joystick(0),
+            (_, b'q') | (_, 3) => Key::Quit,
+            _ => {
+                escape = 0;
+                continue;
+            }
+        };
+        return Ok(Some(key));
+    }
+}
+
+/// Unbuffered, unechoed terminal input until dropped. The terminal has no
+/// escape sequence for this, so it goes through `stty`.
+struct RawMode {
+    saved: String,
+}
+impl RawMode {
+    fn enter() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        // with isig off Ctrl-C arrives as a key, so quitting that way
+        // still puts the terminal back
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        // hide the cursor
+        print!("\x1b[?25l");
+        Ok(RawMode { saved: saved.trim().to_string() })
+    }
+}
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[?25h");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+fn stty(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !out.status.success() {
+        let msg = "stty failed, is stdin a terminal?";
+        return Err(io::Error::new(io::ErrorKind::Other, msg));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+fn draw(out: &mut impl Write, arcade: &Arcade, status: &str) -> io::Result<()> {
+    // home the cursor and clear the screen
+    write!(out, "\x1b[H\x1b[2J{}", arcade.render())?;
+    writeln!(out, "score {:<8} blocks {}", arcade.score(), arcade.blocks())?;
+    writeln!(out, "{}", status)?;
+    out.flush()
+}
+
+fn main() -> Result<(), String> {
+    let path = env::args().nth(1).unwrap_or_else(|| "input/day13".into());
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("can't read {}: {}", path, e))?;
+    let mut code = text
+        .trim()
+        .split(',')
+        .map(|s| s.parse())
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|e| format!("{}: {}", path, e))?;
+    // two quarters for free play
+    code[0] = 2;
+    let mut game = Program::new(code);
+    let mut arcade = Arcade::boot(&mut game)?;
+    let raw = RawMode::enter().map_err(|e| e.to_string())?;
+    let (stdin, stdout) = (io::stdin(), io::stdout());
+    let (mut input, mut out) = (stdin.lock(), stdout.lock());
+    let help = "left/right or a/d to move, space or s to wait, q to quit";
+    while !arcade.halted() {
+        draw(&mut out, &arcade, help).map_err(|e| e.to_string())?;
+        match read_key(&mut input).map_err(|e| e.to_string())? {
+            Some(Key::Joystick(dir)) => {
+                arcade.tick(&mut game, dir)?;
+            }
+            Some(Key::Quit) | None => break,
+        }
+    }
+    let status = if arcade.blocks() == 0 {
+        "you cleared the board!"
+    } else if arcade.halted() {
+        "game over"
+    } else {
+        "bye"
+    };
+    draw(&mut out, &arcade, status).map_err(|e| e.to_string())?;
+    drop(raw);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_key, Key};
+    #[test]
+    fn keys() {
+        let mut input: &[u8] = b"a\x1b[Cxs\x1b[A\x03\x1ba\x1b[dq\x1bq";
+        let mut keys = Vec::new();
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(
+            keys,
+            vec![
+                Key::Joystick(-1),
+                Key::Joystick(1),
+                Key::Joystick(0),
+                Key::Quit,
+                Key::Joystick(-1),
+                Key::Joystick(1),
+                Key::Quit,
+                Key::Quit
+            ]
+        );
+    }
+}
@@ -0,0 +1,712 @@
+//! Compiler for a small language targeting intcode:
+//!
+//! ```text
+//! fn main() {
+//!     let n = input();
+//!     while n > 0 {
+//!         output(square(n));
+//!         n = n - 1;
+//!     }
+//! }
+//! fn square(x) { return x * x; }
+//! ```
+//!
+//! Values are integers; comparisons give 0 or 1 and conditions are true when
+//! non-zero. Every function gets a frame addressed through the relative
+//! base: `[rb]` holds the return address, `[rb+1]..` the parameters and then
+//! locals and temporaries. A call moves the base past the caller's live
+//! slots and the callee leaves its result in `[rb+1]`.
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Sym(&'static str),
+}
+const SYMBOLS: [&str; 17] = [
+    "<=", ">=", "==", "!=", "(", ")", "{", "}", ",", ";", "=", "+", "-", "*",
+    "<", ">", "!",
+];
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    for (line_no, line) in source.lines().enumerate() {
+        let line_no = line_no + 1;
+        let line = line.split("//").next().unwrap();
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let len = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let num = rest[..len].parse().map_err(|_| {
+                    format!("line {}: number too large", line_no)
+                })?;
+                tokens.push((Token::Num(num), line_no));
+                len
+            } else if rest.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            {
+                let len = rest
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                tokens.push((Token::Ident(rest[..len].to_string()), line_no));
+                len
+            } else if let Some(sym) =
+                SYMBOLS.iter().find(|s| rest.starts_with(*s))
+            {
+                tokens.push((Token::Sym(sym), line_no));
+                sym.len()
+            } else {
+                let c = rest.chars().next().unwrap();
+                return Err(format!("line {}: unexpected '{}'", line_no, c));
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Equal,
+    NotEqual,
+}
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Var(String, usize),
+    Input,
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Bin(Box<Expr>, BinOp, Box<Expr>),
+    Call(String, Vec<Expr>, usize),
+}
+#[derive(Debug)]
+enum Stmt {
+    Let(String, Expr),
+    Assign(String, Expr, usize),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Output(Expr),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+#[derive(Debug)]
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    line: usize,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |&(_, line)| line)
+    }
+    fn error<T>(&self, what: &str) -> Result<T, String> {
+        match self.peek() {
+            Some(token) => Err(format!(
+                "line {}: expected {}, found {:?}",
+                self.line(),
+                what,
+                token
+            )),
+            None => Err(format!(
+                "line {}: expected {}, found end",
+                self.line(),
+                what
+            )),
+        }
+    }
+    fn eat(&mut self, sym: &str) -> bool {
+        match self.peek() {
+            Some(Token::Sym(s)) if *s == sym => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+    fn expect(&mut self, sym: &str) -> Result<(), String> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", sym))
+        }
+    }
+    fn keyword(&mut self, word: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(ident)) if ident == word => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+    fn ident(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => {
+                let ident = ident.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => self.error("a name"),
+        }
+    }
+    fn program(&mut self) -> Result<Vec<Function>, String> {
+        let mut functions = Vec::new();
+        while self.peek().is_some() {
+            let line = self.line();
+            if !self.keyword("fn") {
+                return self.error("'fn'");
+            }
+            let name = self.ident()?;
+            self.expect("(")?;
+            let mut params = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    params.push(self.ident()?);
+                    if self.eat(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            let body = self.block()?;
+            functions.push(Function { name, params, body, line });
+        }
+        Ok(functions)
+    }
+    fn block(&mut self) -> Result<Vec<Stmt>, String> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+    fn stmt(&mut self) -> Result<Stmt, String> {
+        let line = self.line();
+        let stmt = if self.keyword("let") {
+            let name = self.ident()?;
+            self.expect("=")?;
+            Stmt::Let(name, self.expr()?)
+        } else if self.keyword("if") {
+            let cond = self.expr()?;
+            let then = self.block()?;
+            let els = if !self.keyword("else") {
+                Vec::new()
+            } else if let Some(Token::Ident(word)) = self.peek() {
+                if word != "if" {
+                    return self.error("'{' or 'if'");
+                }
+                vec![self.stmt()?]
+            } else {
+                self.block()?
+            };
+            return Ok(Stmt::If(cond, then, els));
+        } else if self.keyword("while") {
+            let cond = self.expr()?;
+            return Ok(Stmt::While(cond, self.block()?));
+        } else if self.keyword("output") {
+            self.expect("(")?;
+            let value = self.expr()?;
+            self.expect(")")?;
+            Stmt::Output(value)
+        } else if self.keyword("return") {
+            if self.peek() == Some(&Token::Sym(";")) {
+                Stmt::Return(None)
+            } else {
+                Stmt::Return(Some(self.expr()?))
+            }
+        } else if let (Some(Token::Ident(name)), Some((Token::Sym("="), _))) =
+            (self.peek(), self.tokens.get(self.pos + 1))
+        {
+            let name = name.clone();
+            self.pos += 2;
+            Stmt::Assign(name, self.expr()?, line)
+        } else {
+            Stmt::Expr(self.expr()?)
+        };
+        self.expect(";")?;
+        Ok(stmt)
+    }
+    fn expr(&mut self) -> Result<Expr, String> {
+        let lhs = self.sum()?;
+        let op = [
+            ("<", BinOp::Less),
+            (">", BinOp::Greater),
+            ("<=", BinOp::LessEq),
+            (">=", BinOp::GreaterEq),
+            ("==", BinOp::Equal),
+            ("!=", BinOp::NotEqual),
+        ]
+        .iter()
+        .find(|(sym, _)| self.eat(sym));
+        match op {
+            Some(&(_, op)) => {
+                Ok(Expr::Bin(Box::new(lhs), op, Box::new(self.sum()?)))
+            }
+            None => Ok(lhs),
+        }
+    }
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = if self.eat("+") {
+                BinOp::Add
+            } else if self.eat("-") {
+                BinOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Bin(Box::new(lhs), op, Box::new(self.product()?));
+        }
+    }
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while self.eat("*") {
+            lhs = Expr::Bin(Box::new(lhs), BinOp::Mul, Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.eat("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+    fn primary(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        match self.peek().cloned() {
+            Some(Token::Num(num)) => {
+                self.pos += 1;
+                Ok(Expr::Num(num))
+            }
+            Some(Token::Sym("(")) => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                if !self.eat("(") {
+                    return Ok(Expr::Var(name, line));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                if name == "input" && args.is_empty() {
+                    Ok(Expr::Input)
+                } else {
+                    Ok(Expr::Call(name, args, line))
+                }
+            }
+            _ => self.error("an expression"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Imm(i64),
+    Slot(usize),
+}
+impl Operand {
+    fn mode(self) -> i64 {
+        match self {
+            Operand::Imm(_) => 1,
+            Operand::Slot(_) => 2,
+        }
+    }
+    fn value(self) -> i64 {
+        match self {
+            Operand::Imm(val) => val,
+            Operand::Slot(slot) => slot as i64,
+        }
+    }
+}
+struct Codegen<'a> {
+    code: Vec<i64>,
+    // cells to patch with a label's address once known
+    fixups: Vec<(usize, usize)>,
+    labels: Vec<Option<usize>>,
+    functions: &'a HashMap<String, (usize, usize)>,
+    vars: HashMap<String, usize>,
+    // first free slot in the current frame
+    top: usize,
+}
+impl<'a> Codegen<'a> {
+    fn new_label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+    fn emit(&mut self, op: i64, operands: &[Operand]) {
+        let modes: i64 = operands
+            .iter()
+            .enumerate()
+            .map(|(i, o)| o.mode() * 10_i64.pow(i as u32 + 2))
+            .sum();
+        self.code.push(op + modes);
+        self.code.extend(operands.iter().map(|o| o.value()));
+    }
+    fn emit_jump(&mut self, op: i64, cond: Operand, label: usize) {
+        self.emit(op, &[cond, Operand::Imm(0)]);
+        self.fixups.push((self.code.len() - 1, label));
+    }
+    fn temp(&mut self) -> usize {
+        self.top += 1;
+        self.top - 1
+    }
+    fn var(&self, name: &str, line: usize) -> Result<usize, String> {
+        self.vars.get(name).cloned().ok_or_else(|| {
+            format!("line {}: unknown variable '{}'", line, name)
+        })
+    }
+    fn function(&mut self, function: &Function) -> Result<(), String> {
+        let (label, _) = self.functions[&function.name];
+        self.place(label);
+        self.vars = function
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.clone(), i + 1))
+            .collect();
+        self.top = function.params.len() + 1;
+        self.block(&function.body)?;
+        self.ret(Operand::Imm(0));
+        Ok(())
+    }
+    fn ret(&mut self, value: Operand) {
+        self.emit(1, &[value, Operand::Imm(0), Operand::Slot(1)]);
+        self.emit(5, &[Operand::Imm(1), Operand::Slot(0)]);
+    }
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), String> {
+        for stmt in stmts {
+            // temporaries only live for one statement
+            let locals = self.top;
+            self.stmt(stmt)?;
+            self.top = locals
+                .max(self.vars.values().map(|&v| v + 1).max().unwrap_or(0));
+        }
+        Ok(())
+    }
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        match stmt {
+            Stmt::Let(name, value) => {
+                let value = self.expr(value)?;
+                let slot = self.temp();
+                self.emit(1, &[value, Operand::Imm(0), Operand::Slot(slot)]);
+                self.vars.insert(name.clone(), slot);
+            }
+            Stmt::Assign(name, value, line) => {
+                let slot = self.var(name, *line)?;
+                let value = self.expr(value)?;
+                self.emit(1, &[value, Operand::Imm(0), Operand::Slot(slot)]);
+            }
+            Stmt::If(cond, then, els) => {
+                let (else_label, end) = (self.new_label(), self.new_label());
+                let cond = self.expr(cond)?;
+                self.emit_jump(6, cond, else_label);
+                self.block(then)?;
+                if !els.is_empty() {
+                    self.emit_jump(5, Operand::Imm(1), end);
+                }
+                self.place(else_label);
+                self.block(els)?;
+                self.place(end);
+            }
+            Stmt::While(cond, body) => {
+                let (start, end) = (self.new_label(), self.new_label());
+                self.place(start);
+                let cond = self.expr(cond)?;
+                self.emit_jump(6, cond, end);
+                self.block(body)?;
+                self.emit_jump(5, Operand::Imm(1), start);
+                self.place(end);
+            }
+            Stmt::Output(value) => {
+                let value = self.expr(value)?;
+                self.emit(4, &[value]);
+            }
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value)?,
+                    None => Operand::Imm(0),
+                };
+                self.ret(value);
+            }
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+    fn expr(&mut self, expr: &Expr) -> Result<Operand, String> {
+        let slot = |s| Operand::Slot(s);
+        let operand = match expr {
+            Expr::Num(num) => Operand::Imm(*num),
+            Expr::Var(name, line) => slot(self.var(name, *line)?),
+            Expr::Input => {
+                let t = self.temp();
+                self.emit(3, &[slot(t)]);
+                slot(t)
+            }
+            Expr::Neg(value) => {
+                let value = self.expr(value)?;
+                let t = self.temp();
+                self.emit(2, &[value, Operand::Imm(-1), slot(t)]);
+                slot(t)
+            }
+            Expr::Not(value) => {
+                let value = self.expr(value)?;
+                let t = self.temp();
+                self.emit(8, &[value, Operand::Imm(0), slot(t)]);
+                slot(t)
+            }
+            Expr::Bin(lhs, op, rhs) => {
+                let (a, b) = (self.expr(lhs)?, self.expr(rhs)?);
+                let t = self.temp();
+                match op {
+                    BinOp::Add => self.emit(1, &[a, b, slot(t)]),
+                    BinOp::Mul => self.emit(2, &[a, b, slot(t)]),
+                    BinOp::Sub => {
+                        self.emit(2, &[b, Operand::Imm(-1), slot(t)]);
+                        self.emit(1, &[a, slot(t), slot(t)]);
+                    }
+                    BinOp::Less => self.emit(7, &[a, b, slot(t)]),
+                    BinOp::Greater => self.emit(7, &[b, a, slot(t)]),
+                    BinOp::Equal => self.emit(8, &[a, b, slot(t)]),
+                    BinOp::LessEq | BinOp::GreaterEq | BinOp::NotEqual => {
+                        match op {
+                            BinOp::LessEq => self.emit(7, &[b, a, slot(t)]),
+                            BinOp::GreaterEq => self.emit(7, &[a, b, slot(t)]),
+                            _ => self.emit(8, &[a, b, slot(t)]),
+                        }
+                        self.emit(8, &[slot(t), Operand::Imm(0), slot(t)]);
+                    }
+                }
+                slot(t)
+            }
+            Expr::Call(name, args, line) => {
+                let &(label, arity) =
+                    self.functions.get(name).ok_or_else(|| {
+                        format!("line {}: unknown function '{}'", line, name)
+                    })?;
+                if args.len() != arity {
+                    return Err(format!(
+                        "line {}: '{}' takes {} arguments, got {}",
+                        line,
+                        name,
+                        arity,
+                        args.len()
+                    ));
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let frame = self.top;
+                for (i, arg) in args.into_iter().enumerate() {
+                    self.emit(1, &[arg, Operand::Imm(0), slot(frame + 1 + i)]);
+                }
+                // past the arb, the store and the jump
+                let ret = self.code.len() + 2 + 4 + 3;
+                self.emit(9, &[Operand::Imm(frame as i64)]);
+                self.emit(
+                    1,
+                    &[Operand::Imm(ret as i64), Operand::Imm(0), slot(0)],
+                );
+                self.emit_jump(5, Operand::Imm(1), label);
+                self.emit(9, &[Operand::Imm(-(frame as i64))]);
+                let t = self.temp();
+                self.emit(1, &[slot(frame + 1), Operand::Imm(0), slot(t)]);
+                slot(t)
+            }
+        };
+        Ok(operand)
+    }
+}
+
+/// Compile `source` into an intcode image. The program runs `main` with the
+/// stack placed right after the image, then halts.
+pub fn compile(source: &str) -> Result<Vec<i64>, String> {
+    let functions = Parser { tokens: tokenize(source)?, pos: 0 }.program()?;
+    let mut signatures = HashMap::new();
+    for (i, function) in functions.iter().enumerate() {
+        let signature = (i, function.params.len());
+        if signatures.insert(function.name.clone(), signature).is_some() {
+            return Err(format!(
+                "line {}: '{}' is defined twice",
+                function.line, function.name
+            ));
+        }
+    }
+    match signatures.get("main") {
+        Some((_, 0)) => {}
+        Some(_) => return Err("'main' can't take arguments".to_string()),
+        None => return Err("no 'main' function".to_string()),
+    }
+    let mut gen = Codegen {
+        code: Vec::new(),
+        fixups: Vec::new(),
+        labels: vec![None; functions.len()],
+        functions: &signatures,
+        vars: HashMap::new(),
+        top: 0,
+    };
+    // set the base to the stack, which is patched in at the end
+    gen.emit(9, &[Operand::Imm(0)]);
+    gen.expr(&Expr::Call("main".to_string(), Vec::new(), 0))?;
+    gen.emit(99, &[]);
+    for function in &functions {
+        gen.function(function)?;
+    }
+    let mut code = gen.code;
+    for (cell, label) in gen.fixups {
+        code[cell] = gen.labels[label].unwrap() as i64;
+    }
+    code[1] = code.len() as i64;
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+    use crate::decompile::decompile;
+    use crate::int_code::{Program, Step};
+    fn run(source: &str, inputs: &[i64]) -> Vec<i64> {
+        let mut prog = Program::new(compile(source).unwrap());
+        let mut inputs = inputs.iter();
+        let mut outputs = Vec::new();
+        loop {
+            match prog.step() {
+                Step::Ran => {}
+                Step::Output(out) => outputs.push(out),
+                Step::WaitInput => {
+                    prog.set_input(Some(*inputs.next().expect("out of input")))
+                }
+                Step::Halt => return outputs,
+            }
+        }
+    }
+    #[test]
+    fn arithmetic_and_comparisons() {
+        let source = "
+            fn main() {
+                let a = input();
+                let b = 3;
+                output(a + b * 2 - -1);
+                output(a - b);
+                output(a < b);
+                output(a > b);
+                output(a <= 5);
+                output(a >= 6);
+                output(a == 5);
+                output(a != 5);
+                output(!a);
+            }
+        ";
+        assert_eq!(run(source, &[5]), vec![12, 2, 0, 1, 1, 0, 1, 0, 0]);
+    }
+    #[test]
+    fn control_flow() {
+        let source = "
+            fn main() {
+                let n = input();
+                while n > 0 {
+                    if n == 2 {
+                        output(20);
+                    } else if n == 3 {
+                        output(30);
+                    } else {
+                        output(n);
+                    }
+                    n = n - 1;
+                }
+            }
+        ";
+        assert_eq!(run(source, &[4]), vec![4, 30, 20, 1]);
+    }
+    #[test]
+    fn recursive_functions() {
+        let source = "
+            // fibonacci numbers below the input
+            fn main() {
+                let limit = input();
+                let i = 0;
+                while fib(i) < limit {
+                    output(fib(i));
+                    i = i + 1;
+                }
+            }
+            fn fib(n) {
+                if n < 2 {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+            fn unused(a, b) { return a * b; }
+        ";
+        assert_eq!(run(source, &[30]), vec![0, 1, 1, 2, 3, 5, 8, 13, 21]);
+        let source = "
+            fn main() { output(add3(1, twice(2), 3)); }
+            fn twice(x) { return add3(x, x, 0); }
+            fn add3(a, b, c) { let sum = a + b; return sum + c; }
+        ";
+        assert_eq!(run(source, &[]), vec![8]);
+    }
+    #[test]
+    fn decompiles_calls() {
+        let code = compile("fn main() { output(f(2)); } fn f(x) { return x; }")
+            .unwrap();
+        assert!(decompile(&code).contains("call f"));
+    }
+    #[test]
+    fn errors() {
+        assert_eq!(
+            compile("fn main() {\n output(x);\n}"),
+            Err("line 2: unknown variable 'x'".to_string())
+        );
+        assert_eq!(
+            compile("fn main() { f(1); }\nfn f() {}"),
+            Err("line 1: 'f' takes 0 arguments, got 1".to_string())
+        );
+        assert_eq!(
+            compile("fn main() { output(1) }"),
+            Err("line 1: expected ';', found Sym(\"}\")".to_string())
+        );
+        assert_eq!(compile("fn f() {}"), Err("no 'main' function".to_string()));
+    }
+}
@@ -0,0 +1,365 @@
+use super::int_code::{Memory, Program, Step};
+use std::panic::{self, AssertUnwindSafe};
+
+/// What the suite needs from an intcode implementation.
+pub trait Machine {
+    fn load(code: &[i64]) -> Self;
+    fn input(&mut self, val: i64);
+    /// Run until an output, a missing input or halt, or until `limit`
+    /// instructions have executed in which case `Step::Ran` is returned.
+    fn resume(&mut self, limit: u64) -> Step;
+    fn read(&self, addr: usize) -> i64;
+}
+impl<M: Memory + for<'a> From<&'a [i64]>> Machine for Program<M> {
+    fn load(code: &[i64]) -> Self {
+        Program::with_memory(M::from(code))
+    }
+    fn input(&mut self, val: i64) {
+        self.set_input(Some(val));
+    }
+    fn resume(&mut self, limit: u64) -> Step {
+        for _ in 0..limit {
+            match self.step() {
+                Step::Ran => {}
+                stop => return stop,
+            }
+        }
+        Step::Ran
+    }
+    fn read(&self, addr: usize) -> i64 {
+        Program::read(self, addr)
+    }
+}
+
+struct Case {
+    name: String,
+    code: Vec<i64>,
+    inputs: Vec<i64>,
+    outputs: Vec<i64>,
+    end: Step,
+    memory: Vec<(usize, i64)>,
+}
+impl Case {
+    fn new(name: &str, code: &[i64], outputs: &[i64]) -> Self {
+        Case {
+            name: name.to_string(),
+            code: code.to_vec(),
+            inputs: Vec::new(),
+            outputs: outputs.to_vec(),
+            end: Step::Halt,
+            memory: Vec::new(),
+        }
+    }
+}
+
+// No case needs anywhere near this many instructions between stops.
+const LIMIT: u64 = 10_000;
+/// Run every case against `T`, returning a description of each failure.
+pub fn run_suite<T: Machine>() -> Vec<String> {
+    let mut failures: Vec<String> =
+        cases().iter().filter_map(|case| check::<T>(case).err()).collect();
+    for (name, code) in invalid_programs() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut machine = T::load(&code);
+            machine.input(1);
+            machine.resume(LIMIT)
+        }));
+        if let Ok(step) = result {
+            failures
+                .push(format!("{}: expected a panic, got {:?}", name, step));
+        }
+    }
+    failures
+}
+pub fn case_count() -> usize {
+    cases().len() + invalid_programs().len()
+}
+fn check<T: Machine>(case: &Case) -> Result<(), String> {
+    let fail = |what: String| Err(format!("{}: {}", case.name, what));
+    let mut machine = panic::catch_unwind(|| T::load(&case.code))
+        .map_err(|_| format!("{}: panicked loading", case.name))?;
+    let mut inputs = case.inputs.iter();
+    let mut outputs = Vec::new();
+    let end = panic::catch_unwind(AssertUnwindSafe(|| loop {
+        match machine.resume(LIMIT) {
+            // stop at an unexpected output rather than collecting forever
+            Step::Output(out) if outputs.len() == case.outputs.len() => {
+                return Step::Output(out)
+            }
+            Step::Output(out) => outputs.push(out),
+            Step::WaitInput => match inputs.next() {
+                Some(&val) => machine.input(val),
+                None => return Step::WaitInput,
+            },
+            stop => return stop,
+        }
+    }))
+    .map_err(|_| format!("{}: panicked", case.name))?;
+    if end == Step::Ran {
+        return fail(format!("still running after {} instructions", LIMIT));
+    }
+    if outputs != case.outputs {
+        return fail(format!(
+            "outputs {:?}, expected {:?}",
+            outputs, case.outputs
+        ));
+    }
+    if end != case.end {
+        return fail(format!(
+            "stopped with {:?}, expected {:?}",
+            end, case.end
+        ));
+    }
+    // stopping must be stable when resumed without new input
+    let again = machine.resume(LIMIT);
+    if again != end {
+        return fail(format!("resumed to {:?} after {:?}", again, end));
+    }
+    for &(addr, val) in &case.memory {
+        if machine.read(addr) != val {
+            return fail(format!(
+                "memory[{}] = {}, expected {}",
+                addr,
+                machine.read(addr),
+                val
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Generated cases keep their operands in these cells and address them
+// through each mode, with bases on either side so relative offsets are
+// both positive and negative.
+const A: usize = 30;
+const B: usize = 31;
+const RES: usize = 32;
+const BASES: [i64; 2] = [20, 45];
+const LEN: usize = 60;
+fn operand(mode: i64, addr: usize, val: i64, base: i64) -> i64 {
+    match mode {
+        0 => addr as i64,
+        1 => val,
+        _ => addr as i64 - base,
+    }
+}
+fn image(code: &[i64], cells: &[(usize, i64)]) -> Vec<i64> {
+    let mut image = code.to_vec();
+    image.resize(LEN, 0);
+    for &(addr, val) in cells {
+        image[addr] = val;
+    }
+    image
+}
+type BinaryOp = fn(i64, i64) -> i64;
+fn cases() -> Vec<Case> {
+    let mut cases = Vec::new();
+    let pairs = [(7, -3), (5, 5), (-3, 7), (-(1 << 40), 3)];
+    let binary: [(i64, &str, BinaryOp); 4] = [
+        (1, "add", |a, b| a + b),
+        (2, "mul", |a, b| a * b),
+        (7, "lt", |a, b| (a < b) as i64),
+        (8, "eq", |a, b| (a == b) as i64),
+    ];
+    for &base in &BASES {
+        for &(op, name, f) in &binary {
+            for m1 in 0..3 {
+                for m2 in 0..3 {
+                    for &m3 in &[0, 2] {
+                        for &(a, b) in &pairs {
+                            let code = [
+                                109,
+                                base,
+                                op + 100 * m1 + 1000 * m2 + 10000 * m3,
+                                operand(m1, A, a, base),
+                                operand(m2, B, b, base),
+                                operand(m3, RES, 0, base),
+                                4,
+                                RES as i64,
+                                99,
+                            ];
+                            let mut case = Case::new(
+                                &format!(
+                                    "{} modes {}{}{} base {} ({}, {})",
+                                    name, m1, m2, m3, base, a, b
+                                ),
+                                &image(&code, &[(A, a), (B, b)]),
+                                &[f(a, b)],
+                            );
+                            case.memory.push((RES, f(a, b)));
+                            cases.push(case);
+                        }
+                    }
+                }
+            }
+        }
+        // jumps go to 8 when taken, printing 1 instead of 0
+        for &(op, name) in &[(5, "jnz"), (6, "jz")] {
+            for m1 in 0..3 {
+                for m2 in 0..3 {
+                    for &cond in &[0, 9, -1] {
+                        let taken = (cond != 0) == (op == 5);
+                        let code = [
+                            109,
+                            base,
+                            op + 100 * m1 + 1000 * m2,
+                            operand(m1, A, cond, base),
+                            operand(m2, B, 8, base),
+                            104,
+                            0,
+                            99,
+                            104,
+                            1,
+                            99,
+                        ];
+                        cases.push(Case::new(
+                            &format!(
+                                "{} modes {}{} base {} cond {}",
+                                name, m1, m2, base, cond
+                            ),
+                            &image(&code, &[(A, cond), (B, 8)]),
+                            &[taken as i64],
+                        ));
+                    }
+                }
+            }
+        }
+        for &mode in &[0, 2] {
+            let code = [109, base, 3 + 100 * mode, operand(mode, A, 0, base)];
+            let mut case = Case::new(
+                &format!("in mode {} base {}", mode, base),
+                &image(&[&code[..], &[4, A as i64, 99]].concat(), &[]),
+                &[-42],
+            );
+            case.inputs.push(-42);
+            case.memory.push((A, -42));
+            cases.push(case);
+        }
+        for mode in 0..3 {
+            let code = [109, base, 4 + 100 * mode, operand(mode, A, -17, base)];
+            cases.push(Case::new(
+                &format!("out mode {} base {}", mode, base),
+                &image(&[&code[..], &[99]].concat(), &[(A, -17)]),
+                &[-17],
+            ));
+        }
+        // moving the base by 3 makes [rb] the marked cell after the base
+        for mode in 0..3 {
+            let code = [109, base, 9 + 100 * mode, operand(mode, A, 3, base)];
+            let marked = base as usize + 3;
+            cases.push(Case::new(
+                &format!("arb mode {} base {}", mode, base),
+                &image(
+                    &[&code[..], &[204, 0, 99]].concat(),
+                    &[(A, 3), (marked, 77)],
+                ),
+                &[77],
+            ));
+        }
+    }
+
+    cases.push(Case::new("relative base starts at 0", &[204, 0, 99], &[204]));
+    cases.push(Case::new(
+        "negative relative offset",
+        &[109, 10, 204, -10, 99],
+        &[109],
+    ));
+    cases.push(Case::new(
+        "relative base accumulates",
+        &image(&[109, 25, 109, -5, 204, 0, 109, 0, 204, -1, 99], &[(19, 6)]),
+        &[0, 6],
+    ));
+    cases.push(Case::new(
+        "relative write with negative offset",
+        &image(&[109, 40, 21101, 3, 4, -10, 4, 30, 99], &[]),
+        &[7],
+    ));
+    cases.push(Case::new(
+        "large immediate",
+        &[104, 1_125_899_906_842_624, 99],
+        &[1_125_899_906_842_624],
+    ));
+    cases.push(Case::new(
+        "large product",
+        &[1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0],
+        &[1_219_070_632_396_864],
+    ));
+    cases.push(Case::new(
+        "quine",
+        &image(
+            &[
+                109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006,
+                101, 0, 99,
+            ],
+            &[],
+        ),
+        &[
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101,
+            0, 99,
+        ],
+    ));
+    let mut case =
+        Case::new("self modifying", &[1, 1, 1, 4, 99, 5, 6, 0, 99], &[]);
+    case.memory.push((0, 30));
+    cases.push(case);
+    cases.push(Case::new("halt", &[99], &[]));
+    cases.push(Case::new("halt after output", &[104, 1, 99, 104, 2], &[1]));
+    let mut case = Case::new(
+        "wait for input",
+        &image(&[104, 1, 3, 30, 4, 30, 99], &[]),
+        &[1],
+    );
+    case.end = Step::WaitInput;
+    cases.push(case);
+    let mut case = Case::new(
+        "consume inputs in order",
+        &image(&[3, 30, 3, 31, 2, 30, 31, 32, 4, 32, 99], &[]),
+        &[-6],
+    );
+    case.inputs = vec![2, -3];
+    cases.push(case);
+    cases
+}
+// Programs that break the rules and must panic rather than carry on.
+fn invalid_programs() -> Vec<(String, Vec<i64>)> {
+    let mut programs: Vec<_> = [11101, 11102, 103, 11107, 11108]
+        .iter()
+        .map(|&op| {
+            let code = match op {
+                103 => vec![op, 0, 99],
+                op => vec![op, 1, 2, 0, 99],
+            };
+            (format!("immediate write by {}", op), code)
+        })
+        .collect();
+    let negative: [(&str, &[i64]); 6] = [
+        ("negative position read", &[4, -1, 99]),
+        ("negative position write", &[1101, 1, 1, -1, 99]),
+        ("negative position input", &[3, -1, 99]),
+        ("negative relative read", &[204, -5, 99]),
+        ("negative relative write", &[21101, 7, 0, -5, 99]),
+        ("relative base below zero", &[109, -3, 204, 1, 99]),
+    ];
+    for &(name, code) in &negative {
+        programs.push((name.to_string(), code.to_vec()));
+    }
+    programs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{case_count, run_suite};
+    use crate::int_code::{PagedMemory, Program};
+    #[test]
+    fn flat_memory() {
+        assert_eq!(run_suite::<Program>(), Vec::<String>::new());
+    }
+    #[test]
+    fn paged_memory() {
+        assert_eq!(run_suite::<Program<PagedMemory>>(), Vec::<String>::new());
+    }
+    #[test]
+    fn suite_size() {
+        assert!(case_count() > 500);
+    }
+}
@@ -0,0 +1,203 @@
+use super::int_code::{decode, op_size, Op};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Instruction and branch counts from one or more runs of a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    hits: BTreeMap<usize, u64>,
+    // (taken, not taken) for each conditional jump
+    branches: BTreeMap<usize, (u64, u64)>,
+}
+impl Coverage {
+    pub fn hit(&mut self, addr: usize) {
+        *self.hits.entry(addr).or_default() += 1;
+    }
+    pub fn branch(&mut self, addr: usize, taken: bool) {
+        let counts = self.branches.entry(addr).or_default();
+        if taken {
+            counts.0 += 1;
+        } else {
+            counts.1 += 1;
+        }
+    }
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.hits.get(&addr).cloned().unwrap_or(0)
+    }
+    pub fn executed(&self) -> impl Iterator<Item = usize> + '_ {
+        self.hits.keys().cloned()
+    }
+    /// Conditional jumps that have only been seen going one way.
+    pub fn partial_branches(&self) -> Vec<usize> {
+        self.branches
+            .iter()
+            .filter(|(_, &(taken, not_taken))| taken == 0 || not_taken == 0)
+            .map(|(&addr, _)| addr)
+            .collect()
+    }
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&addr, &hits) in &other.hits {
+            *self.hits.entry(addr).or_default() += hits;
+        }
+        for (&addr, &(taken, not_taken)) in &other.branches {
+            let counts = self.branches.entry(addr).or_default();
+            counts.0 += taken;
+            counts.1 += not_taken;
+        }
+    }
+    /// Instructions in `image` that were never executed.
+    pub fn never_ran(&self, image: &[i64]) -> Vec<usize> {
+        listing(image, self)
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Op(addr, _) if self.hits(addr) == 0 => Some(addr),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Annotated listing of `image` with hit counts, `#####` for
+    /// instructions that never ran and the direction counts of branches.
+    pub fn report(&self, image: &[i64]) -> String {
+        let entries = listing(image, self);
+        let ops = entries.iter().filter(|e| matches!(e, Entry::Op(..))).count();
+        let both_ways = self.branches.len() - self.partial_branches().len();
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{} of {} instructions executed, {} of {} branches went both ways",
+            self.hits.len(),
+            ops,
+            both_ways,
+            self.branches.len()
+        )
+        .unwrap();
+        for entry in entries {
+            match entry {
+                Entry::Op(addr, op) => {
+                    let hits = match self.hits(addr) {
+                        0 => "#####".to_string(),
+                        hits => hits.to_string(),
+                    };
+                    write!(out, "{:>6} {:>8}  {}", addr, hits, op).unwrap();
+                    if let Some((taken, not_taken)) = self.branches.get(&addr) {
+                        write!(
+                            out,
+                            "  (taken {}, not taken {})",
+                            taken, not_taken
+                        )
+                        .unwrap();
+                    }
+                    out.push('\n');
+                }
+                Entry::Data(addr, values) => {
+                    let values: Vec<String> =
+                        values.iter().map(|v| v.to_string()).collect();
+                    writeln!(
+                        out,
+                        "{:>6} {:>8}  data {}",
+                        addr,
+                        "",
+                        values.join(", ")
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+enum Entry {
+    Op(usize, Op),
+    Data(usize, Vec<i64>),
+}
+// Split the image into instructions and data by a linear sweep. Executed
+// addresses are always instruction starts, so a cell that would decode into
+// an instruction overlapping one of them is data.
+fn listing(image: &[i64], coverage: &Coverage) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut addr = 0;
+    while addr < image.len() {
+        let op = decode(image, addr).filter(|op| {
+            coverage.hits.range(addr + 1..addr + op_size(op)).next().is_none()
+        });
+        match op {
+            Some(op) => {
+                let size = op_size(&op);
+                entries.push(Entry::Op(addr, op));
+                addr += size;
+            }
+            None => {
+                match entries.last_mut() {
+                    Some(Entry::Data(start, values))
+                        if *start + values.len() == addr
+                            && values.len() < 8 =>
+                    {
+                        values.push(image[addr])
+                    }
+                    _ => entries.push(Entry::Data(addr, vec![image[addr]])),
+                }
+                addr += 1;
+            }
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+    use crate::int_code::Program;
+    fn run_with_coverage(code: &[i64], input: i64) -> Coverage {
+        let mut prog = Program::new(code.to_vec());
+        prog.collect_coverage();
+        prog.set_input(Some(input));
+        while prog.run().is_some() {}
+        prog.coverage().unwrap().clone()
+    }
+    #[test]
+    fn countdown_report() {
+        let code =
+            [1101, 3, 0, 20, 4, 20, 1001, 20, -1, 20, 1005, 20, 4, 99, 0];
+        let coverage = run_with_coverage(&code, 0);
+        assert_eq!(
+            coverage.report(&code),
+            "\
+5 of 5 instructions executed, 1 of 1 branches went both ways
+     0        1  add 3, 0, [20]
+     4        3  out [20]
+     6        3  add [20], -1, [20]
+    10        3  jnz [20], 4  (taken 2, not taken 1)
+    13        1  halt
+    14           data 0
+"
+        );
+    }
+    #[test]
+    fn merge_runs() {
+        // in [11]; jnz [11], 8; out 0; halt; out 1; halt
+        let code = [3, 11, 1005, 11, 8, 104, 0, 99, 104, 1, 99, 0];
+        let mut zero = run_with_coverage(&code, 0);
+        let one = run_with_coverage(&code, 1);
+        assert_eq!(zero.never_ran(&code), vec![8, 10]);
+        assert_eq!(one.never_ran(&code), vec![5, 7]);
+        assert_eq!(zero.partial_branches(), vec![2]);
+        zero.merge(&one);
+        assert!(zero.never_ran(&code).is_empty());
+        assert!(zero.partial_branches().is_empty());
+        assert_eq!(zero.hits(2), 2);
+    }
+    #[test]
+    fn halt_counted_once() {
+        let mut prog = Program::new(vec![99]);
+        prog.collect_coverage();
+        for _ in 0..3 {
+            assert_eq!(prog.run(), None);
+        }
+        assert_eq!(prog.coverage().unwrap().hits(0), 1);
+        // setting the head resumes the machine, so the halt counts again
+        prog.set_head(0);
+        prog.run();
+        assert_eq!(prog.coverage().unwrap().hits(0), 2);
+    }
+}
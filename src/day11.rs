@@ -1,14 +1,22 @@
+use super::image::{Image, Rgb, Style};
 use super::int_code::Program;
 use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
 
 struct Robot {
     pos: (i32, i32),
     dir: (i32, i32),
     program: Program,
 }
-enum Turn {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Turn {
     Left,
     Right,
+    Straight,
+    Around,
 }
 #[derive(Clone, Copy)]
 enum Color {
@@ -24,6 +32,8 @@ impl Robot {
             // (0,1) (-1,0) (0,-1) (1,0) (0,1)
             Turn::Left => self.dir = (-self.dir.1, self.dir.0),
             Turn::Right => self.dir = (self.dir.1, -self.dir.0),
+            Turn::Straight => {}
+            Turn::Around => self.dir = (-self.dir.0, -self.dir.1),
         }
     }
     fn paint(&mut self, camera_input: Color) -> Option<Color> {
@@ -48,40 +58,346 @@ impl Robot {
         self.pos = (self.pos.0 + self.dir.0, self.pos.1 + self.dir.1);
     }
 }
-fn print_paint(paint_map: &HashMap<(i32, i32), Color>) {
+fn paint_text(paint_map: &HashMap<(i32, i32), Color>) -> String {
     use std::cmp;
-    let mut min_x = i32::max_value();
-    let mut max_x = i32::min_value();
-    let mut min_y = i32::max_value();
-    let mut max_y = i32::min_value();
+    let mut min_x = i32::MAX;
+    let mut max_x = i32::MIN;
+    let mut min_y = i32::MAX;
+    let mut max_y = i32::MIN;
     for &(x, y) in paint_map.keys() {
         min_x = cmp::min(min_x, x);
         min_y = cmp::min(min_y, y);
         max_x = cmp::max(max_x, x);
         max_y = cmp::max(max_y, y);
     }
+    let mut text = String::new();
     for y in (min_y..=max_y).rev() {
         for x in min_x..=max_x {
             if let Some(Color::White) = paint_map.get(&(x, y)) {
-                print!("#");
+                text.push('#');
             } else {
-                print!(".");
+                text.push('.');
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+fn print_paint(paint_map: &HashMap<(i32, i32), Color>) {
+    print!("{}", paint_text(paint_map));
+}
+fn paint_image(paint_map: &HashMap<(i32, i32), Color>, scale: usize) -> Image {
+    let cells = paint_map.iter().map(|(&(x, y), &c)| ((x as i64, y as i64), c));
+    let palette = |c: &Color| match c {
+        Color::Black => None,
+        Color::White => Some([255, 255, 255]),
+    };
+    Image::from_cells(
+        cells,
+        palette,
+        &Style { scale, y_up: true, ..Style::default() },
+    )
+}
+
+/// How a robot's program encodes colors and turns.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    // color codes the program reads and paints, with how to draw them
+    pub palette: Vec<(i64, char)>,
+    pub turns: Vec<(i64, Turn)>,
+    pub start_color: i64,
+    // color of panels never painted
+    pub background: i64,
+}
+impl Default for Rules {
+    // black and white, 0 turns left and 1 right, as on day 11
+    fn default() -> Self {
+        Rules {
+            palette: vec![(0, '.'), (1, '#')],
+            turns: vec![(0, Turn::Left), (1, Turn::Right)],
+            start_color: 0,
+            background: 0,
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub painted: usize,
+    pub paints: usize,
+    // panels painted more than once
+    pub repainted: usize,
+    // min and max corner of the painted panels
+    pub bounds: Option<((i32, i32), (i32, i32))>,
+}
+/// One paint, turn and move of the robot: the panel painted, the color, and
+/// the heading it moved off in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub pos: (i32, i32),
+    pub paint: i64,
+    pub dir: (i32, i32),
+}
+pub struct Painter {
+    robot: Robot,
+    rules: Rules,
+    // every paint of a panel as (step, color), in order
+    hull: HashMap<(i32, i32), Vec<(usize, i64)>>,
+    moves: Vec<Move>,
+}
+impl Painter {
+    pub fn new(program: Program, rules: Rules) -> Self {
+        Painter {
+            robot: Robot::new(program),
+            rules,
+            hull: HashMap::new(),
+            moves: Vec::new(),
+        }
+    }
+    /// Paint, turn and move until the program halts. Fails on a paint or
+    /// turn code outside the rules.
+    pub fn run(&mut self) -> Result<(), String> {
+        loop {
+            let step = self.moves.len();
+            let camera = self.color(self.robot.pos);
+            let paint = match self.robot.program.run_input(Some(camera)) {
+                Some(paint) => paint,
+                None => return Ok(()),
+            };
+            if !self.rules.palette.iter().any(|&(code, _)| code == paint) {
+                return Err(format!("step {}: unknown color {}", step, paint));
+            }
+            let turn_code =
+                self.robot.program.run_input(None).ok_or_else(|| {
+                    format!("step {}: no turn after paint", step)
+                })?;
+            let turn = self
+                .rules
+                .turns
+                .iter()
+                .find(|&&(code, _)| code == turn_code)
+                .map(|&(_, turn)| turn)
+                .ok_or_else(|| {
+                    format!("step {}: unknown turn {}", step, turn_code)
+                })?;
+            let pos = self.robot.pos;
+            self.hull.entry(pos).or_default().push((step, paint));
+            self.robot.turn(turn);
+            self.robot.step();
+            self.moves.push(Move { pos, paint, dir: self.robot.dir });
+        }
+    }
+    pub fn color(&self, pos: (i32, i32)) -> i64 {
+        match self.hull.get(&pos).and_then(|h| h.last()) {
+            Some(&(_, color)) => color,
+            None if pos == (0, 0) => self.rules.start_color,
+            None => self.rules.background,
+        }
+    }
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+    pub fn replay(&self) -> Replay<'_> {
+        let mut bounds = ((0, 0), (0, 0));
+        for m in &self.moves {
+            let next = (m.pos.0 + m.dir.0, m.pos.1 + m.dir.1);
+            for &(x, y) in &[m.pos, next] {
+                let ((x0, y0), (x1, y1)) = bounds;
+                bounds = ((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y)));
+            }
+        }
+        Replay { painter: self, bounds }
+    }
+    pub fn history(&self, pos: (i32, i32)) -> &[(usize, i64)] {
+        self.hull.get(&pos).map_or(&[], |h| h)
+    }
+    pub fn stats(&self) -> Stats {
+        let bounds =
+            self.hull.keys().fold(None, |bounds, &(x, y)| match bounds {
+                None => Some(((x, y), (x, y))),
+                Some(((x0, y0), (x1, y1))) => {
+                    Some(((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y))))
+                }
+            });
+        Stats {
+            painted: self.hull.len(),
+            paints: self.hull.values().map(|h| h.len()).sum(),
+            repainted: self.hull.values().filter(|h| h.len() > 1).count(),
+            bounds,
+        }
+    }
+    /// The painted area, top row first, using the palette's characters.
+    pub fn render(&self) -> String {
+        let ((x0, y0), (x1, y1)) = match self.stats().bounds {
+            Some(bounds) => bounds,
+            None => return String::new(),
+        };
+        let mut out = String::new();
+        for y in (y0..=y1).rev() {
+            for x in x0..=x1 {
+                let color = self.color((x, y));
+                let c =
+                    self.rules.palette.iter().find(|&&(code, _)| code == color);
+                out.push(c.map_or('?', |&(_, c)| c));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// The hull and robot after the first `step` moves.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub step: usize,
+    pub hull: HashMap<(i32, i32), i64>,
+    pub pos: (i32, i32),
+    pub dir: (i32, i32),
+}
+/// A painting run played back move by move, every frame covering the whole
+/// area the robot visits.
+pub struct Replay<'a> {
+    painter: &'a Painter,
+    // min and max corner of the visited panels
+    bounds: ((i32, i32), (i32, i32)),
+}
+impl<'a> Replay<'a> {
+    /// Every `every`th frame from the start, and the last one.
+    pub fn frames(&self, every: usize) -> impl Iterator<Item = Frame> + 'a {
+        let moves = &self.painter.moves;
+        let mut frame =
+            Frame { step: 0, hull: HashMap::new(), pos: (0, 0), dir: (0, 1) };
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let shown = frame.clone();
+            for m in moves[frame.step..].iter().take(every.max(1)) {
+                frame.hull.insert(m.pos, m.paint);
+                frame.pos = (m.pos.0 + m.dir.0, m.pos.1 + m.dir.1);
+                frame.dir = m.dir;
+                frame.step += 1;
             }
+            // the last frame is shown even when it isn't on the beat
+            done = shown.step == moves.len();
+            Some(shown)
+        })
+    }
+    fn color(&self, frame: &Frame, pos: (i32, i32)) -> i64 {
+        let rules = &self.painter.rules;
+        match frame.hull.get(&pos) {
+            Some(&color) => color,
+            None if pos == (0, 0) => rules.start_color,
+            None => rules.background,
+        }
+    }
+    /// The frame as text, top row first, with the robot drawn as an arrow.
+    pub fn text(&self, frame: &Frame) -> String {
+        let ((x0, y0), (x1, y1)) = self.bounds;
+        let palette = &self.painter.rules.palette;
+        let mut out = String::new();
+        for y in (y0..=y1).rev() {
+            for x in x0..=x1 {
+                let c = if (x, y) == frame.pos {
+                    match frame.dir {
+                        (0, 1) => '^',
+                        (1, 0) => '>',
+                        (0, -1) => 'v',
+                        _ => '<',
+                    }
+                } else {
+                    let color = self.color(frame, (x, y));
+                    let c = palette.iter().find(|&&(code, _)| code == color);
+                    c.map_or('?', |&(_, c)| c)
+                };
+                out.push(c);
+            }
+            out.push('\n');
+        }
+        out
+    }
+    /// The frame as a picture with `palette` colors and the robot in
+    /// `robot`.
+    pub fn image(
+        &self,
+        frame: &Frame,
+        palette: impl Fn(i64) -> Option<Rgb>,
+        robot: Rgb,
+        style: &Style,
+    ) -> Image {
+        let ((x0, y0), (x1, y1)) = self.bounds;
+        let mut cells = Vec::new();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let color = if (x, y) == frame.pos {
+                    Some(robot)
+                } else {
+                    palette(self.color(frame, (x, y)))
+                };
+                cells.push(((x as i64, y as i64), color));
+            }
+        }
+        let style = Style { y_up: true, ..*style };
+        Image::from_cells(cells, |&color| color, &style)
+    }
+    /// Play every `every`th frame to a terminal, redrawing the screen and
+    /// waiting `delay` between frames.
+    pub fn play(
+        &self,
+        out: &mut impl Write,
+        every: usize,
+        delay: Duration,
+    ) -> io::Result<()> {
+        for frame in self.frames(every) {
+            // home the cursor and clear the screen
+            write!(out, "\x1b[H\x1b[2J{}", self.text(&frame))?;
+            writeln!(out, "step {}/{}", frame.step, self.painter.moves.len())?;
+            out.flush()?;
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+    /// Save every `every`th frame drawn by `draw` to `dir` as numbered
+    /// files `frame00000.ext` and so on, for stitching into an animation
+    /// with an outside tool. Returns the paths written.
+    pub fn save_frames(
+        &self,
+        dir: &str,
+        ext: &str,
+        every: usize,
+        draw: impl Fn(&Frame) -> Image,
+    ) -> io::Result<Vec<String>> {
+        fs::create_dir_all(dir)?;
+        let mut paths = Vec::new();
+        for (i, frame) in self.frames(every).enumerate() {
+            let path = format!("{}/frame{:05}.{}", dir, i, ext);
+            draw(&frame).save(&path)?;
+            paths.push(path);
         }
-        println!();
+        Ok(paths)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::paint_image;
+    use super::paint_text;
     use super::print_paint;
     use super::Color;
+    use super::Move;
+    use super::Painter;
     use super::Program;
     use super::Robot;
+    use super::Rules;
+    use super::Stats;
+    use super::Style;
     use super::Turn;
+    use crate::compiler::compile;
+    use crate::ocr;
     use std::collections::HashMap;
     use std::fs;
     use std::iter;
+    use std::time::Duration;
     #[test]
     fn robot_turns() {
         let mut robot = Robot::new(Program::new(Vec::new()));
@@ -138,5 +454,149 @@ mod tests {
             camera_input = *painted.get(&robot.pos).unwrap_or(&Color::Black);
         }
         print_paint(&painted);
+        let letters = ocr::read(&ocr::grid(&paint_text(&painted)));
+        assert_eq!(letters, Ok("JFBERBUH".to_string()));
+        let image = paint_image(&painted, 4);
+        assert_eq!((image.width, image.height), (43, 6));
+        assert!(image.to_png().len() > 43 * 6 * 16 * 3);
+    }
+    #[test]
+    fn day11_painter() {
+        let mut code: Vec<i64> = fs::read_to_string("input/day11")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        code.extend(iter::repeat(0).take(1000));
+        let mut painter =
+            Painter::new(Program::new(code.clone()), Rules::default());
+        painter.run().unwrap();
+        assert_eq!(painter.stats().painted, 2343);
+        let rules = Rules { start_color: 1, ..Rules::default() };
+        let mut painter = Painter::new(Program::new(code), rules);
+        painter.run().unwrap();
+        let stats = painter.stats();
+        assert_eq!(stats.bounds, Some(((0, -5), (42, 0))));
+        assert_eq!(painter.render().lines().count(), 6);
+        assert!(painter.render().lines().all(|line| line.len() == 43));
+        let letters = ocr::read(&ocr::grid(&painter.render()));
+        assert_eq!(letters, Ok("JFBERBUH".to_string()));
+    }
+    #[test]
+    fn custom_rules() {
+        let code = compile(
+            "
+            fn main() {
+                paint(2, 5);
+                paint(1, 7);
+                echo(5);
+                paint(1, 5);
+                paint(3, 5);
+            }
+            fn paint(color, turn) {
+                let camera = input();
+                output(color);
+                output(turn);
+            }
+            // paint the color seen
+            fn echo(turn) {
+                output(input());
+                output(turn);
+            }
+        ",
+        )
+        .unwrap();
+        let rules = Rules {
+            palette: vec![(0, ' '), (1, '+'), (2, '*')],
+            turns: vec![(5, Turn::Straight), (7, Turn::Around)],
+            start_color: 2,
+            background: 0,
+        };
+        let mut painter = Painter::new(Program::new(code), rules);
+        assert_eq!(painter.run(), Err("step 4: unknown color 3".to_string()));
+        assert_eq!(painter.history((0, 0)), &[(0, 2), (2, 2)]);
+        assert_eq!(painter.history((0, 1)), &[(1, 1)]);
+        assert_eq!(painter.history((0, -2)), &[]);
+        assert_eq!(painter.render(), "+\n*\n+\n");
+        assert_eq!(
+            painter.stats(),
+            Stats {
+                painted: 3,
+                paints: 4,
+                repainted: 1,
+                bounds: Some(((0, -1), (0, 1)))
+            }
+        );
+    }
+    #[test]
+    fn replay() {
+        let code = compile(
+            "
+            fn main() {
+                let n = 0;
+                while n < 3 {
+                    input();
+                    output(1);
+                    output(1);
+                    n = n + 1;
+                }
+            }
+        ",
+        )
+        .unwrap();
+        let mut painter = Painter::new(Program::new(code), Rules::default());
+        painter.run().unwrap();
+        assert_eq!(
+            painter.moves()[1],
+            Move { pos: (1, 0), paint: 1, dir: (0, -1) }
+        );
+        let replay = painter.replay();
+        let frames: Vec<_> =
+            replay.frames(2).map(|f| (f.step, replay.text(&f))).collect();
+        assert_eq!(
+            frames,
+            vec![
+                (0, "^.\n..\n".to_string()),
+                (2, "##\n.v\n".to_string()),
+                (3, "##\n<#\n".to_string()),
+            ]
+        );
+        let mut out = Vec::new();
+        replay.play(&mut out, 3, Duration::from_millis(0)).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("\x1b[2J").count(), 2);
+        assert!(out.ends_with("##\n<#\nstep 3/3\n"));
+    }
+    #[test]
+    fn day11_frames() {
+        let mut code: Vec<i64> = fs::read_to_string("input/day11")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        code.extend(iter::repeat(0).take(1000));
+        let rules = Rules { start_color: 1, ..Rules::default() };
+        let mut painter = Painter::new(Program::new(code), rules);
+        painter.run().unwrap();
+        let replay = painter.replay();
+        let dir = std::env::temp_dir()
+            .join(format!("day11_frames_{}", std::process::id()));
+        let white = |color| if color == 1 { Some([255; 3]) } else { None };
+        let saved =
+            replay.save_frames(dir.to_str().unwrap(), "ppm", 100, |frame| {
+                replay.image(frame, white, [255, 0, 0], &Style::default())
+            });
+        // read back what is needed and clean up before asserting
+        let files: Vec<_> = saved.as_ref().map_or(Vec::new(), |paths| {
+            paths.iter().map(|path| fs::read(path).unwrap()).collect()
+        });
+        let _ = fs::remove_dir_all(&dir);
+        let paths = saved.unwrap();
+        assert_eq!(paths.len(), replay.frames(100).count());
+        assert!(paths[1].ends_with("frame00001.ppm"));
+        // every frame covers the same area
+        assert!(files.iter().all(|file| file.len() == files[0].len()));
     }
 }
@@ -1,38 +1,334 @@
-use std::collections::HashMap;
+use super::image::{Image, Style};
+use super::int_code::{Memory, Program, Stop};
 
-fn draw_display(display: &HashMap<(i64, i64), i64>) {
-    use std::cmp;
-    let mut min_x = i64::max_value();
-    let mut max_x = i64::min_value();
-    let mut min_y = i64::max_value();
-    let mut max_y = i64::min_value();
-    for &(x, y) in display.keys() {
-        min_x = cmp::min(min_x, x);
-        min_y = cmp::min(min_y, y);
-        max_x = cmp::max(max_x, x);
-        max_y = cmp::max(max_y, y);
-    }
-    for y in min_y..=max_y {
-        for x in min_x..=max_x {
-            match display.get(&(x, y)) {
-                Some(1) => print!("|"),
-                Some(2) => print!("#"),
-                Some(3) => print!("_"),
-                Some(4) => print!("o"),
-                None | Some(_) => print!(" "),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tile {
+    Empty,
+    Wall,
+    Block,
+    Paddle,
+    Ball,
+}
+impl Tile {
+    pub fn from_id(id: i64) -> Option<Tile> {
+        match id {
+            0 => Some(Tile::Empty),
+            1 => Some(Tile::Wall),
+            2 => Some(Tile::Block),
+            3 => Some(Tile::Paddle),
+            4 => Some(Tile::Ball),
+            _ => None,
+        }
+    }
+    pub fn symbol(self) -> char {
+        match self {
+            Tile::Empty => ' ',
+            Tile::Wall => '|',
+            Tile::Block => '#',
+            Tile::Paddle => '_',
+            Tile::Ball => 'o',
+        }
+    }
+}
+/// A change to the screen made by one output triple.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Tile { pos: (usize, usize), old: Tile, new: Tile },
+    Score { old: i64, new: i64 },
+}
+/// The arcade cabinet's screen: a frame buffer sized from the first frame
+/// the game draws, and the score display.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arcade {
+    width: usize,
+    height: usize,
+    tiles: Vec<Tile>,
+    score: i64,
+    blocks: usize,
+    ball: Option<(usize, usize)>,
+    paddle: Option<(usize, usize)>,
+    halted: bool,
+}
+impl Arcade {
+    /// A screen just large enough for the tiles in `frame`, with the frame
+    /// drawn on it.
+    pub fn new(frame: &[i64]) -> Result<Arcade, String> {
+        let (mut width, mut height) = (0, 0);
+        for tile in frame.chunks(3) {
+            if let [x, y, _] = *tile {
+                if (x, y) != (-1, 0) && x >= 0 && y >= 0 {
+                    width = width.max(x as usize + 1);
+                    height = height.max(y as usize + 1);
+                }
+            }
+        }
+        let mut arcade = Arcade {
+            width,
+            height,
+            tiles: vec![Tile::Empty; width * height],
+            score: 0,
+            blocks: 0,
+            ball: None,
+            paddle: None,
+            halted: false,
+        };
+        arcade.apply(frame)?;
+        Ok(arcade)
+    }
+    /// Run `game` until it first asks for the joystick, or halts, and build
+    /// the screen from what it drew.
+    pub fn boot<M: Memory>(game: &mut Program<M>) -> Result<Arcade, String> {
+        let mut outputs = Vec::new();
+        let stop = game.run_until(&mut outputs, |_, _| false);
+        let mut arcade = Arcade::new(&outputs)?;
+        arcade.halted = stop == Stop::Halt;
+        Ok(arcade)
+    }
+    /// Move the joystick, -1 left, 0 neutral or 1 right, and draw the
+    /// frame that follows.
+    pub fn tick<M: Memory>(
+        &mut self,
+        game: &mut Program<M>,
+        joystick: i64,
+    ) -> Result<Vec<Event>, String> {
+        let mut outputs = Vec::new();
+        game.set_input(Some(joystick));
+        let stop = game.run_until(&mut outputs, |_, _| false);
+        self.halted = stop == Stop::Halt;
+        self.apply(&outputs)
+    }
+    /// Draw a batch of `x, y, id` outputs and return what changed.
+    pub fn apply(&mut self, outputs: &[i64]) -> Result<Vec<Event>, String> {
+        if outputs.len() % 3 != 0 {
+            return Err("outputs end in the middle of a tile".to_string());
+        }
+        let mut events = Vec::new();
+        for tile in outputs.chunks(3) {
+            let (x, y, id) = (tile[0], tile[1], tile[2]);
+            if (x, y) == (-1, 0) {
+                if id != self.score {
+                    events.push(Event::Score { old: self.score, new: id });
+                    self.score = id;
+                }
+                continue;
+            }
+            let new = Tile::from_id(id).ok_or_else(|| {
+                format!("unknown tile id {} at ({}, {})", id, x, y)
+            })?;
+            if x < 0
+                || y < 0
+                || x as usize >= self.width
+                || y as usize >= self.height
+            {
+                return Err(format!(
+                    "tile ({}, {}) is off the {}x{} screen",
+                    x, y, self.width, self.height
+                ));
+            }
+            let pos = (x as usize, y as usize);
+            let old = std::mem::replace(
+                &mut self.tiles[pos.1 * self.width + pos.0],
+                new,
+            );
+            if old == new {
+                continue;
+            }
+            events.push(Event::Tile { pos, old, new });
+            match old {
+                Tile::Block => self.blocks -= 1,
+                Tile::Ball if self.ball == Some(pos) => self.ball = None,
+                Tile::Paddle if self.paddle == Some(pos) => self.paddle = None,
+                _ => {}
+            }
+            match new {
+                Tile::Block => self.blocks += 1,
+                Tile::Ball => self.ball = Some(pos),
+                Tile::Paddle => self.paddle = Some(pos),
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn tile(&self, x: usize, y: usize) -> Tile {
+        self.tiles[y * self.width + x]
+    }
+    pub fn score(&self) -> i64 {
+        self.score
+    }
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+    pub fn ball(&self) -> Option<(usize, usize)> {
+        self.ball
+    }
+    pub fn paddle(&self) -> Option<(usize, usize)> {
+        self.paddle
+    }
+    /// Whether the game program has halted, after the last `boot` or
+    /// `tick`.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+    /// The screen as text, one row per line.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for row in self.tiles.chunks(self.width.max(1)) {
+            out.extend(row.iter().map(|tile| tile.symbol()));
+            out.push('\n');
+        }
+        out
+    }
+    pub fn image(&self, scale: usize) -> Image {
+        let palette = |tile: &Tile| match tile {
+            Tile::Empty => None,
+            Tile::Wall => Some([128, 128, 128]),
+            Tile::Block => Some([200, 80, 40]),
+            Tile::Paddle => Some([60, 120, 220]),
+            Tile::Ball => Some([255, 255, 255]),
+        };
+        let cells = self.tiles.iter().enumerate().map(|(i, &tile)| {
+            (((i % self.width) as i64, (i / self.width) as i64), tile)
+        });
+        Image::from_cells(cells, palette, &Style { scale, ..Style::default() })
+    }
+}
+/// Picks joystick moves for an `Arcade` each time the game asks.
+pub trait JoystickStrategy {
+    /// -1 for left, 0 for neutral or 1 for right.
+    fn choose(&mut self, arcade: &Arcade) -> i64;
+}
+/// Keep the paddle under the ball.
+pub struct TrackBall;
+impl JoystickStrategy for TrackBall {
+    fn choose(&mut self, arcade: &Arcade) -> i64 {
+        match (arcade.ball(), arcade.paddle()) {
+            (Some(ball), Some(paddle)) => {
+                (ball.0 as i64 - paddle.0 as i64).signum()
             }
+            _ => 0,
         }
-        println!();
     }
 }
+/// Head for where the falling ball will reach the paddle's row, bouncing
+/// it off the side walls. Blocks in the way are ignored, which the paddle
+/// makes up for as the ball gets closer.
+#[derive(Default)]
+pub struct PredictLanding {
+    last_ball: Option<(usize, usize)>,
+}
+impl JoystickStrategy for PredictLanding {
+    fn choose(&mut self, arcade: &Arcade) -> i64 {
+        let (ball, paddle) = match (arcade.ball(), arcade.paddle()) {
+            (Some(ball), Some(paddle)) => (ball, paddle),
+            _ => return 0,
+        };
+        let last = self.last_ball.replace(ball).unwrap_or(ball);
+        let (dx, dy) =
+            (ball.0 as i64 - last.0 as i64, ball.1 as i64 - last.1 as i64);
+        let target = if dy > 0 {
+            // the walls are the outer columns
+            let (left, right) = (1, arcade.width() as i64 - 2);
+            let mut x = ball.0 as i64;
+            let mut dx = dx;
+            for _ in ball.1 + 1..paddle.1 {
+                if !(left..=right).contains(&(x + dx)) {
+                    dx = -dx;
+                }
+                x += dx;
+            }
+            x
+        } else {
+            ball.0 as i64
+        };
+        (target - paddle.0 as i64).signum()
+    }
+}
+/// Random moves from a seeded xorshift generator.
+pub struct Random {
+    state: u64,
+}
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed.max(1) }
+    }
+}
+impl JoystickStrategy for Random {
+    fn choose(&mut self, _arcade: &Arcade) -> i64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state % 3) as i64 - 1
+    }
+}
+/// Replay recorded moves, such as the `inputs` of a `Report`, then hold
+/// still.
+pub struct Scripted {
+    moves: Vec<i64>,
+    next: usize,
+}
+impl Scripted {
+    pub fn new(moves: Vec<i64>) -> Self {
+        Scripted { moves, next: 0 }
+    }
+}
+impl JoystickStrategy for Scripted {
+    fn choose(&mut self, _arcade: &Arcade) -> i64 {
+        let joystick = self.moves.get(self.next).copied().unwrap_or(0);
+        self.next += 1;
+        joystick
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub score: i64,
+    pub blocks_left: usize,
+    // frames drawn, the opening screen included
+    pub frames: usize,
+    pub inputs: Vec<i64>,
+}
+/// Boot `game` and let `strategy` play it until the program halts.
+pub fn autopilot<M: Memory>(
+    mut game: Program<M>,
+    strategy: &mut dyn JoystickStrategy,
+) -> Result<Report, String> {
+    let mut arcade = Arcade::boot(&mut game)?;
+    let mut inputs = Vec::new();
+    while !arcade.halted() {
+        let joystick = strategy.choose(&arcade);
+        arcade.tick(&mut game, joystick)?;
+        inputs.push(joystick);
+    }
+    Ok(Report {
+        score: arcade.score(),
+        blocks_left: arcade.blocks(),
+        frames: inputs.len() + 1,
+        inputs,
+    })
+}
 #[cfg(test)]
 mod tests {
-    #[allow(unused_imports)]
-    use super::draw_display;
-    use crate::int_code::Program;
+    use super::autopilot;
+    use super::Arcade;
+    use super::Event;
+    use super::JoystickStrategy;
+    use super::PredictLanding;
+    use super::Random;
+    use super::Report;
+    use super::Scripted;
+    use super::Tile;
+    use super::TrackBall;
+    use crate::int_code::{Program, Stop};
+    use crate::transcript::{assert_golden, Recorder};
     use std::collections::HashMap;
     use std::fs;
     use std::iter;
+    use std::time::Instant;
     #[test]
     fn day13_part1() {
         let code: Vec<i64> = fs::read_to_string("input/day13")
@@ -43,14 +339,12 @@ mod tests {
             .chain(iter::repeat(0).take(2000))
             .collect();
         let mut game = Program::new(code);
-        let mut display = HashMap::new();
-        while let (Some(x), Some(y), Some(id)) =
-            (game.run(), game.run(), game.run())
-        {
-            display.insert((x, y), id);
-        }
-        let ans1 = display.values().filter(|&&id| id == 2).count();
-        assert_eq!(ans1, 462);
+        let arcade = Arcade::boot(&mut game).unwrap();
+        assert!(arcade.halted());
+        assert_eq!(arcade.blocks(), 462);
+        assert_eq!((arcade.width(), arcade.height()), (45, 24));
+        let image = arcade.image(2);
+        assert!(image.to_ppm().starts_with(b"P6\n90 48\n255\n"));
     }
     #[test]
     fn day13_part2() {
@@ -62,40 +356,193 @@ mod tests {
             .chain(iter::repeat(0).take(2000))
             .collect();
         code[0] = 2;
-        let mut game = Program::new(code);
-        let mut display = HashMap::new();
-        let mut score = 0;
+        let report = autopilot(Program::new(code), &mut TrackBall).unwrap();
+        assert_eq!(report.blocks_left, 0);
+        assert_eq!(report.score, 23981);
+    }
+    #[test]
+    fn strategies() {
+        let mut strategies: Vec<Box<dyn JoystickStrategy>> = vec![
+            Box::new(TrackBall),
+            Box::new(PredictLanding::default()),
+            Box::new(Random::new(13)),
+            Box::new(Scripted::new(vec![1, 1, -1])),
+        ];
+        let mut reports = Vec::new();
+        for strategy in &mut strategies {
+            let report = autopilot(load_game(), strategy.as_mut()).unwrap();
+            assert_eq!(report.frames, report.inputs.len() + 1);
+            reports.push(report);
+        }
+        assert_eq!((reports[0].score, reports[0].blocks_left), (23981, 0));
+        assert_eq!((reports[1].score, reports[1].blocks_left), (23981, 0));
+        // the ball's path doesn't depend on the paddle, but waiting where it
+        // will land takes fewer moves than following it
+        assert_eq!(reports[0].frames, reports[1].frames);
+        let moves = |r: &Report| r.inputs.iter().filter(|&&i| i != 0).count();
+        assert!(moves(&reports[1]) < moves(&reports[0]));
+        assert!(reports[2].blocks_left > 0);
+        // the script runs out and the paddle is held still
+        assert!(reports[3].inputs.starts_with(&[1, 1, -1]));
+        assert!(reports[3].inputs[3..].iter().all(|&i| i == 0));
+        // replaying a recorded game plays it out the same
+        let mut replay = Scripted::new(reports[0].inputs.clone());
+        assert_eq!(autopilot(load_game(), &mut replay).unwrap(), reports[0]);
+    }
+    #[test]
+    fn arcade_events() {
+        let mut arcade =
+            Arcade::new(&[0, 0, 1, 2, 1, 2, 1, 1, 4, -1, 0, 0]).unwrap();
+        assert_eq!((arcade.width(), arcade.height()), (3, 2));
+        assert_eq!(arcade.render(), "|  \n o#\n");
+        assert_eq!((arcade.blocks(), arcade.ball()), (1, Some((1, 1))));
+        // the ball hits the block
+        let events =
+            arcade.apply(&[1, 1, 0, 2, 1, 0, 2, 1, 4, -1, 0, 5, 0, 0, 1]);
+        assert_eq!(
+            events,
+            Ok(vec![
+                Event::Tile { pos: (1, 1), old: Tile::Ball, new: Tile::Empty },
+                Event::Tile { pos: (2, 1), old: Tile::Block, new: Tile::Empty },
+                Event::Tile { pos: (2, 1), old: Tile::Empty, new: Tile::Ball },
+                Event::Score { old: 0, new: 5 },
+            ])
+        );
+        assert_eq!((arcade.blocks(), arcade.ball()), (0, Some((2, 1))));
+        assert_eq!(arcade.paddle(), None);
+        assert_eq!(arcade.tile(2, 1), Tile::Ball);
+        assert_eq!(arcade.score(), 5);
+        assert_eq!(
+            arcade.apply(&[3, 0, 1]),
+            Err("tile (3, 0) is off the 3x2 screen".to_string())
+        );
+        assert_eq!(
+            arcade.apply(&[0, 0, 7]),
+            Err("unknown tile id 7 at (0, 0)".to_string())
+        );
+        assert_eq!(
+            arcade.apply(&[0, 0]),
+            Err("outputs end in the middle of a tile".to_string())
+        );
+    }
+    #[test]
+    fn day13_part2_transcript() {
+        let mut code: Vec<i64> = fs::read_to_string("input/day13")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .chain(iter::repeat(0).take(2000))
+            .collect();
+        code[0] = 2;
+        // the opening screen and the first 50 joystick moves
+        let mut game = Recorder::new(Program::new(code));
         let mut paddle_x = 0;
         let mut ball_x = 0;
+        for _ in 0..50 {
+            while let (Some(x), Some(_), Some(id)) =
+                (game.run(), game.run(), game.run())
+            {
+                match id {
+                    3 => paddle_x = x,
+                    4 => ball_x = x,
+                    _ => {}
+                }
+            }
+            game.set_input(Some((ball_x - paddle_x).signum()));
+        }
+        assert_golden("input/transcripts/day13_part2", game.transcript(), 3);
+    }
+    #[derive(Default)]
+    struct Screen {
+        tiles: HashMap<(i64, i64), i64>,
+        blocks: usize,
+        score: i64,
+        paddle_x: i64,
+        ball_x: i64,
+    }
+    impl Screen {
+        fn draw(&mut self, x: i64, y: i64, id: i64) {
+            if (x, y) == (-1, 0) {
+                self.score = id;
+                return;
+            }
+            if self.tiles.insert((x, y), id) == Some(2) {
+                self.blocks -= 1;
+            }
+            match id {
+                2 => self.blocks += 1,
+                3 => self.paddle_x = x,
+                4 => self.ball_x = x,
+                _ => {}
+            }
+        }
+        fn joystick(&self) -> i64 {
+            (self.ball_x - self.paddle_x).signum()
+        }
+    }
+    // re-enters the machine for every output value
+    fn play_per_output(mut game: Program) -> Screen {
+        let mut screen = Screen::default();
         loop {
             while let (Some(x), Some(y), Some(id)) =
                 (game.run(), game.run(), game.run())
             {
-                if (x, y) == (-1, 0) {
-                    score = id;
-                } else {
-                    display.insert((x, y), id);
-                    if id == 3 {
-                        paddle_x = x;
-                    }
-                    if id == 4 {
-                        ball_x = x;
-                    }
-                }
+                screen.draw(x, y, id);
+            }
+            if screen.blocks == 0 {
+                return screen;
+            }
+            game.set_input(Some(screen.joystick()));
+        }
+    }
+    // takes each frame's outputs in one batch
+    fn play_batched(mut game: Program) -> Screen {
+        let mut screen = Screen::default();
+        let mut outputs = Vec::new();
+        loop {
+            outputs.clear();
+            let stop = game.run_until(&mut outputs, |_, _| false);
+            for tile in outputs.chunks(3) {
+                screen.draw(tile[0], tile[1], tile[2]);
             }
-            // draw_display(&display);
-            use std::cmp::Ordering;
-            let input = match paddle_x.cmp(&ball_x) {
-                Ordering::Less => 1,
-                Ordering::Equal => 0,
-                Ordering::Greater => -1,
-            };
-            game.set_input(Some(input));
-            let blocks = display.values().filter(|&&id| id == 2).count();
-            if blocks == 0 {
-                break;
+            if stop == Stop::Halt {
+                return screen;
             }
+            game.set_input(Some(screen.joystick()));
+        }
+    }
+    fn load_game() -> Program {
+        let mut code: Vec<i64> = fs::read_to_string("input/day13")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        code[0] = 2;
+        Program::new(code)
+    }
+    #[test]
+    fn day13_part2_batched() {
+        let screen = play_batched(load_game());
+        assert_eq!((screen.score, screen.blocks), (23981, 0));
+    }
+    #[test]
+    #[ignore]
+    fn game_loop_benchmark() {
+        // best of a few rounds, the whole game is only tens of milliseconds
+        fn time(name: &str, play: fn(Program) -> Screen) {
+            let best = (0..5)
+                .map(|_| {
+                    let start = Instant::now();
+                    assert_eq!(play(load_game()).score, 23981);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap();
+            println!("{:<12} {:?}", name, best);
         }
-        assert_eq!(score, 23981);
+        time("per output", play_per_output);
+        time("batched", play_batched);
     }
 }
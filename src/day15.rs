@@ -1,6 +1,8 @@
+use super::explore::{explore, Discovery, Search};
+use super::image::{Image, Style};
 use super::int_code::Program;
 use std::collections::{HashMap, HashSet, VecDeque};
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Tile {
     Floor,
     Wall,
@@ -13,6 +15,7 @@ enum Dir {
     S,
     W,
 }
+#[derive(Clone)]
 struct Robot {
     program: Program,
     pos: (i32, i32),
@@ -47,7 +50,7 @@ fn create_map(robot: &mut Robot) -> HashMap<(i32, i32), Tile> {
         if let Some((unvisited, dir)) = [Dir::N, Dir::S, Dir::E, Dir::W]
             .iter()
             .map(|&d| (get_dir_coordinate(robot.pos, d), d))
-            .find(|(new_pos, _)| !map.contains_key(&new_pos))
+            .find(|(new_pos, _)| !map.contains_key(new_pos))
         {
             match robot.move_dir(dir) {
                 tile @ Tile::Floor | tile @ Tile::Oxygen => {
@@ -74,6 +77,22 @@ fn create_map(robot: &mut Robot) -> HashMap<(i32, i32), Tile> {
     }
     map
 }
+// breadth-first over forked droids, one fork per direction at every floor
+fn explore_map(robot: Robot) -> Vec<Discovery<(Robot, Tile), Dir>> {
+    explore(
+        (robot, Tile::Floor),
+        &[Dir::N, Dir::S, Dir::E, Dir::W],
+        Search::Breadth,
+        |(robot, _), &dir| {
+            let mut robot = robot.clone();
+            match robot.move_dir(dir) {
+                Tile::Wall => None,
+                tile => Some((robot, tile)),
+            }
+        },
+        |(robot, _)| robot.pos,
+    )
+}
 fn get_dir_coordinate(start: (i32, i32), dir: Dir) -> (i32, i32) {
     match dir {
         Dir::N => (start.0, start.1 + 1),
@@ -133,9 +152,25 @@ fn map_oxygen_spread(
     visited
 }
 
+fn map_image(map: &HashMap<(i32, i32), Tile>, scale: usize) -> Image {
+    let cells = map.iter().map(|(&(x, y), &tile)| ((x as i64, y as i64), tile));
+    let palette = |tile: &Tile| match tile {
+        Tile::Wall => Some([90, 90, 90]),
+        Tile::Floor => Some([230, 230, 230]),
+        Tile::Oxygen => Some([40, 90, 230]),
+    };
+    Image::from_cells(
+        cells,
+        palette,
+        &Style { scale, y_up: true, ..Style::default() },
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::create_map;
+    use super::explore_map;
+    use super::map_image;
     use super::map_oxygen_spread;
     use super::search_map_for_oxygen;
     use super::Program;
@@ -154,6 +189,8 @@ mod tests {
         let map = create_map(&mut robot);
         let ans1 = search_map_for_oxygen(&map);
         assert_eq!(ans1, 258);
+        let svg = map_image(&map, 8).to_svg();
+        assert_eq!(svg.matches("<rect").count(), map.len() + 1);
 
         let oxygen_pos = map
             .iter()
@@ -166,4 +203,22 @@ mod tests {
         let ans2 = oxygen_spread.values().max().unwrap();
         assert_eq!(*ans2, 372);
     }
+    #[test]
+    fn day15_part1_explored() {
+        let code: Vec<i64> = fs::read_to_string("input/day15")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let robot = Robot { pos: (0, 0), program: Program::new(code) };
+        let found = explore_map(robot);
+        let oxygen = found.iter().find(|d| d.state.1 == Tile::Oxygen).unwrap();
+        assert_eq!(oxygen.inputs.len(), 258);
+        let map = create_map(&mut oxygen.state.0.clone());
+        assert_eq!(
+            map.values().filter(|&&t| t != Tile::Wall).count(),
+            found.len()
+        );
+    }
 }
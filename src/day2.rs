@@ -1,4 +1,4 @@
-fn run_program(mut code: Vec<usize>) -> Vec<usize> {
+pub fn run_program(mut code: Vec<usize>) -> Vec<usize> {
     let mut head = 0;
     loop {
         let op = code[head];
@@ -19,6 +19,7 @@ fn run_program(mut code: Vec<usize>) -> Vec<usize> {
 #[cfg(test)]
 mod tests {
     use super::run_program;
+    use crate::int_code::Program;
     use std::fs;
 
     #[test]
@@ -48,6 +49,20 @@ mod tests {
         assert_eq!(ans[0], 5_110_675);
     }
     #[test]
+    fn day2_part1_int_code() {
+        let code: Vec<i64> = fs::read_to_string("input/day2")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let mut prog = Program::new(code);
+        prog.write(1, 12);
+        prog.write(2, 2);
+        assert_eq!(prog.run(), None);
+        assert_eq!(prog.read(0), 5_110_675);
+    }
+    #[test]
     fn day2_part2() {
         let program: Vec<usize> = fs::read_to_string("input/day2")
             .unwrap()
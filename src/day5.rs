@@ -16,11 +16,11 @@ enum Op {
     Halt,
 }
 
-fn run_program(code: &mut Vec<i32>, input: i32) -> Vec<i32> {
+pub fn run_program(code: &mut [i32], input: i32) -> Vec<i32> {
     let mut head = 0;
     let mut output = Vec::new();
     loop {
-        let op = parse_op(&code, head);
+        let op = parse_op(code, head);
         let value = |p: Param| match p {
             Param::Pos(pos) => code[pos],
             Param::Im(im) => im,
@@ -20,7 +20,7 @@ fn calculate_orbits(orbits: &[&str], start: &str, end: &str) -> (u32, u32) {
         .iter()
         .zip(end_path.iter())
         .filter_map(|(sta, end)| if sta == end { Some(sta) } else { None })
-        .last()
+        .next_back()
         .cloned()
         .unwrap_or_default();
     let transfers_needed = orbit_counts[start] - orbit_counts[&last_shared]
@@ -48,7 +48,7 @@ fn get_path(name: &str, orbit_map: &HashMap<&str, &str>) -> Vec<String> {
         path.push(name.into());
         path
     } else {
-        return vec![name.into()];
+        vec![name.into()]
     }
 }
 #[cfg(test)]
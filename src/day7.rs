@@ -1,21 +1,383 @@
-use super::int_code::Program;
+use super::int_code::{Program, Step};
+use itertools::Itertools;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
 
-fn run_program_chain(
-    mut input: i64,
-    programs: &mut [&mut Program],
-) -> Option<i64> {
-    for p in programs {
-        input = p.run_input(Some(input))?;
-    }
-    Some(input)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    // one pass through the stages
+    Single,
+    // the last stage feeds the first until the amplifiers halt
+    Feedback,
+}
+#[derive(Clone)]
+pub struct AmplifierArray {
+    amps: Vec<Program>,
+    mode: Mode,
+    history: Vec<Vec<i64>>,
+}
+impl AmplifierArray {
+    /// One amplifier running `code` per phase setting, in order.
+    pub fn new(code: &[i64], phases: &[i64], mode: Mode) -> Self {
+        assert!(!phases.is_empty(), "no amplifiers");
+        let mut array = AmplifierArray::empty(mode);
+        for &phase in phases {
+            array.push(code, phase);
+        }
+        array
+    }
+    fn empty(mode: Mode) -> Self {
+        AmplifierArray { amps: Vec::new(), mode, history: Vec::new() }
+    }
+    fn push(&mut self, code: &[i64], phase: i64) {
+        let mut amp = Program::new(code.to_vec());
+        amp.run_input(Some(phase));
+        self.amps.push(amp);
+        self.history.push(Vec::new());
+    }
+    // Pass `signal` through the last stage once, as the first pass of `run`
+    // would.
+    fn run_last(&mut self, signal: i64) -> Option<i64> {
+        let out = self.amps.last_mut()?.run_input(Some(signal))?;
+        self.history.last_mut().unwrap().push(out);
+        Some(out)
+    }
+    /// Feed `input` to the first stage and return the last signal sent to
+    /// the thrusters, if any.
+    pub fn run(&mut self, input: i64) -> Option<i64> {
+        let mut signal = input;
+        loop {
+            for (amp, history) in self.amps.iter_mut().zip(&mut self.history) {
+                signal = match amp.run_input(Some(signal)) {
+                    Some(out) => out,
+                    None => return self.history.last()?.last().cloned(),
+                };
+                history.push(signal);
+            }
+            if self.mode == Mode::Single {
+                return Some(signal);
+            }
+        }
+    }
+    /// Signals sent by each stage, first stage first.
+    pub fn history(&self) -> &[Vec<i64>] {
+        &self.history
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranked {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+// Keep the `top` best, highest signal first and ties in phase order.
+fn rank(best: &mut Vec<Ranked>, candidate: Ranked, top: usize) {
+    let at = best
+        .iter()
+        .position(|r| {
+            (-r.signal, &r.phases) > (-candidate.signal, &candidate.phases)
+        })
+        .unwrap_or(best.len());
+    if at < top {
+        best.insert(at, candidate);
+        best.truncate(top);
+    }
+}
+/// The `top` settings of `count` distinct phases drawn from `candidates`.
+/// Settings sharing a prefix share the amplifiers that run it: in feedback
+/// mode the chain is snapshotted after each stage's first pass, and in
+/// single-pass mode, where stages only depend on their phase and input, each
+/// stage's output is memoized.
+pub fn best_phases(
+    code: &[i64],
+    candidates: &[i64],
+    count: usize,
+    mode: Mode,
+    top: usize,
+) -> Vec<Ranked> {
+    struct Search<'a> {
+        code: &'a [i64],
+        candidates: &'a [i64],
+        count: usize,
+        top: usize,
+        memo: HashMap<(i64, i64), Option<i64>>,
+        best: Vec<Ranked>,
+    }
+    impl Search<'_> {
+        fn extend(
+            &mut self,
+            phases: &mut Vec<i64>,
+            mut array: AmplifierArray,
+            signal: i64,
+        ) {
+            if phases.len() == self.count {
+                let signal = match array.mode {
+                    Mode::Single => Some(signal),
+                    Mode::Feedback => array.run(signal),
+                };
+                if let Some(signal) = signal {
+                    let ranked = Ranked { phases: phases.clone(), signal };
+                    rank(&mut self.best, ranked, self.top);
+                }
+                return;
+            }
+            let unused: Vec<i64> = self
+                .candidates
+                .iter()
+                .cloned()
+                .filter(|phase| !phases.contains(phase))
+                .collect();
+            for (i, &phase) in unused.iter().enumerate() {
+                // the last branch can have the chain rather than a copy, and
+                // single-pass stages don't need it at all so it stays empty
+                let mut next = if i + 1 == unused.len() {
+                    mem::replace(
+                        &mut array,
+                        AmplifierArray::empty(Mode::Single),
+                    )
+                } else {
+                    array.clone()
+                };
+                let out = match next.mode {
+                    Mode::Single => {
+                        let code = self.code;
+                        *self.memo.entry((phase, signal)).or_insert_with(|| {
+                            let mut single =
+                                AmplifierArray::empty(Mode::Single);
+                            single.push(code, phase);
+                            single.run_last(signal)
+                        })
+                    }
+                    Mode::Feedback => {
+                        next.push(self.code, phase);
+                        next.run_last(signal)
+                    }
+                };
+                // a stage that halts without a signal ends the whole branch
+                if let Some(out) = out {
+                    phases.push(phase);
+                    self.extend(phases, next, out);
+                    phases.pop();
+                }
+            }
+        }
+    }
+    let mut search = Search {
+        code,
+        candidates,
+        count,
+        top,
+        memo: HashMap::new(),
+        best: Vec::new(),
+    };
+    search.extend(&mut Vec::new(), AmplifierArray::empty(mode), 0);
+    search.best
+}
+/// `best_phases` by running every permutation from scratch.
+pub fn best_phases_brute_force(
+    code: &[i64],
+    candidates: &[i64],
+    count: usize,
+    mode: Mode,
+    top: usize,
+) -> Vec<Ranked> {
+    let mut best = Vec::new();
+    for phases in candidates.iter().cloned().permutations(count) {
+        if let Some(signal) = AmplifierArray::new(code, &phases, mode).run(0) {
+            rank(&mut best, Ranked { phases, signal }, top);
+        }
+    }
+    best
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endpoint {
+    // signals given to `Topology::run`
+    Input,
+    // the thrusters, collecting the network's result
+    Output,
+    Amp(usize),
+}
+/// An amplifier network, written one statement per line:
+///
+/// ```text
+/// # two amplifiers in a loop, reporting every signal
+/// amp a 5
+/// amp b 6
+/// in -> a
+/// a -> b, out
+/// b -> a
+/// ```
+///
+/// `amp NAME PHASE` declares an amplifier and `FROM -> TO, ...` sends every
+/// output of `FROM` to each `TO`. `in` and `out` are the network's input and
+/// the thrusters. An amplifier fed from several places reads the signals in
+/// the order they were sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topology {
+    amps: Vec<(String, i64)>,
+    edges: Vec<(Endpoint, Endpoint)>,
+}
+impl Topology {
+    /// Parse and validate a description, listing every problem found.
+    pub fn parse(text: &str) -> Result<Topology, String> {
+        let mut amps: Vec<(String, i64)> = Vec::new();
+        let mut edges = Vec::new();
+        let mut errors = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line_no = line_no + 1;
+            let line = line.split('#').next().unwrap().trim();
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["amp", name, phase] => {
+                    let error = if ["in", "out"].contains(name) {
+                        format!("'{}' is reserved", name)
+                    } else if amps.iter().any(|(n, _)| n == name) {
+                        format!("'{}' is declared twice", name)
+                    } else if let Ok(phase) = phase.parse() {
+                        amps.push((name.to_string(), phase));
+                        continue;
+                    } else {
+                        format!("bad phase '{}'", phase)
+                    };
+                    errors.push(format!("line {}: {}", line_no, error));
+                }
+                _ if line.contains("->") => {
+                    let mut sides = line.splitn(2, "->");
+                    let from = sides.next().unwrap().trim();
+                    for to in sides.next().unwrap().split(',') {
+                        edges.push((
+                            line_no,
+                            from.to_string(),
+                            to.trim().to_string(),
+                        ));
+                    }
+                }
+                _ => errors
+                    .push(format!("line {}: can't read '{}'", line_no, line)),
+            }
+        }
+        let endpoint = |name: &str| match name {
+            "in" => Some(Endpoint::Input),
+            "out" => Some(Endpoint::Output),
+            _ => amps.iter().position(|(n, _)| n == name).map(Endpoint::Amp),
+        };
+        let mut resolved = Vec::new();
+        for (line_no, from, to) in edges {
+            for name in [&from, &to] {
+                if endpoint(name).is_none() {
+                    errors.push(format!(
+                        "line {}: edge to or from unknown amp '{}'",
+                        line_no, name
+                    ));
+                }
+            }
+            match (endpoint(&from), endpoint(&to)) {
+                (Some(Endpoint::Output), _) => errors.push(format!(
+                    "line {}: 'out' can't send signals",
+                    line_no
+                )),
+                (_, Some(Endpoint::Input)) => errors.push(format!(
+                    "line {}: 'in' can't receive signals",
+                    line_no
+                )),
+                (Some(from), Some(to)) => resolved.push((from, to)),
+                _ => {}
+            }
+        }
+        let topology = Topology { amps, edges: resolved };
+        let reached = topology.reachable();
+        for (i, (name, _)) in topology.amps.iter().enumerate() {
+            if !reached[i] {
+                errors
+                    .push(format!("amp '{}' can't be reached from 'in'", name));
+            }
+        }
+        if errors.is_empty() {
+            Ok(topology)
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+    // amps with a path from the input
+    fn reachable(&self) -> Vec<bool> {
+        let mut reached = vec![false; self.amps.len()];
+        let mut todo = vec![Endpoint::Input];
+        while let Some(from) = todo.pop() {
+            for &(_, to) in self.edges.iter().filter(|(f, _)| *f == from) {
+                if let Endpoint::Amp(amp) = to {
+                    if !reached[amp] {
+                        reached[amp] = true;
+                        todo.push(to);
+                    }
+                }
+            }
+        }
+        reached
+    }
+    /// Run one amplifier per declaration on `code`, send `inputs` from `in`
+    /// and return every signal that reached `out`. The network runs until no
+    /// amplifier can make progress, each getting a turn in declaration order.
+    pub fn run(&self, code: &[i64], inputs: &[i64]) -> Vec<i64> {
+        let mut amps: Vec<_> = self
+            .amps
+            .iter()
+            .map(|&(_, phase)| {
+                let mut amp = Program::new(code.to_vec());
+                amp.set_input(Some(phase));
+                (amp, VecDeque::new())
+            })
+            .collect();
+        let mut thrust = Vec::new();
+        let mut send =
+            |from, signal, amps: &mut Vec<(Program, VecDeque<i64>)>| {
+                for &(_, to) in self.edges.iter().filter(|(f, _)| *f == from) {
+                    match to {
+                        Endpoint::Amp(amp) => amps[amp].1.push_back(signal),
+                        Endpoint::Output => thrust.push(signal),
+                        Endpoint::Input => unreachable!(),
+                    }
+                }
+            };
+        for &signal in inputs {
+            send(Endpoint::Input, signal, &mut amps);
+        }
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for i in 0..amps.len() {
+                loop {
+                    let (amp, queue) = &mut amps[i];
+                    match amp.step() {
+                        Step::Ran => {}
+                        Step::Output(signal) => {
+                            send(Endpoint::Amp(i), signal, &mut amps)
+                        }
+                        Step::WaitInput => match queue.pop_front() {
+                            Some(signal) => amp.set_input(Some(signal)),
+                            None => break,
+                        },
+                        Step::Halt => break,
+                    }
+                    progress = true;
+                }
+            }
+        }
+        thrust
+    }
 }
 #[cfg(test)]
 mod tests {
-    use super::run_program_chain;
+    use super::best_phases;
+    use super::best_phases_brute_force;
+    use super::AmplifierArray;
+    use super::Mode;
     use super::Program;
+    use super::Topology;
+    use crate::compiler::compile;
+    use crate::coverage::Coverage;
     use itertools::Itertools;
-    use std::cmp;
     use std::fs;
+    use std::time::Instant;
     #[test]
     fn day7_part1() {
         let amp_code: Vec<i64> = fs::read_to_string("input/day7")
@@ -25,25 +387,15 @@ mod tests {
             .map(|s| s.parse().unwrap())
             .collect();
 
-        let mut max_out = 0;
-        for set in (0..5).permutations(5) {
-            let mut prog_a = Program::new(amp_code.clone());
-            let mut prog_b = Program::new(amp_code.clone());
-            let mut prog_c = Program::new(amp_code.clone());
-            let mut prog_d = Program::new(amp_code.clone());
-            let mut prog_e = Program::new(amp_code.clone());
-            prog_a.run_input(Some(set[0]));
-            prog_b.run_input(Some(set[1]));
-            prog_c.run_input(Some(set[2]));
-            prog_d.run_input(Some(set[3]));
-            prog_e.run_input(Some(set[4]));
-            let out_a = prog_a.run_input(Some(0)).unwrap();
-            let out_b = prog_b.run_input(Some(out_a)).unwrap();
-            let out_c = prog_c.run_input(Some(out_b)).unwrap();
-            let out_d = prog_d.run_input(Some(out_c)).unwrap();
-            let out_e = prog_e.run_input(Some(out_d)).unwrap();
-            max_out = cmp::max(max_out, out_e);
-        }
+        let max_out = (0..5)
+            .permutations(5)
+            .map(|set| {
+                let mut amps =
+                    AmplifierArray::new(&amp_code, &set, Mode::Single);
+                amps.run(0).unwrap()
+            })
+            .max()
+            .unwrap();
         assert_eq!(max_out, 914_828);
     }
     #[test]
@@ -54,36 +406,246 @@ mod tests {
             .split(',')
             .map(|s| s.parse().unwrap())
             .collect();
-        let mut max_out = 0;
-
-        for set in (5..10).permutations(5) {
-            let mut prog_a = Program::new(amp_code.clone());
-            let mut prog_b = Program::new(amp_code.clone());
-            let mut prog_c = Program::new(amp_code.clone());
-            let mut prog_d = Program::new(amp_code.clone());
-            let mut prog_e = Program::new(amp_code.clone());
-            prog_a.run_input(Some(set[0]));
-            prog_b.run_input(Some(set[1]));
-            prog_c.run_input(Some(set[2]));
-            prog_d.run_input(Some(set[3]));
-            prog_e.run_input(Some(set[4]));
-
+        let max_out = (5..10)
+            .permutations(5)
+            .map(|set| {
+                let mut amps =
+                    AmplifierArray::new(&amp_code, &set, Mode::Feedback);
+                amps.run(0).unwrap()
+            })
+            .max()
+            .unwrap();
+        assert_eq!(max_out, 17_956_613);
+    }
+    #[test]
+    fn example_day7_amplifiers() {
+        let code =
+            [3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+        let mut amps =
+            AmplifierArray::new(&code, &[4, 3, 2, 1, 0], Mode::Single);
+        assert_eq!(amps.run(0), Some(43210));
+        assert_eq!(
+            amps.history(),
+            &[vec![4], vec![43], vec![432], vec![4321], vec![43210]]
+        );
+        let mut amps = AmplifierArray::new(&code, &[1, 0], Mode::Single);
+        assert_eq!(amps.run(5), Some(510));
+        let code = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4,
+            27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let mut amps =
+            AmplifierArray::new(&code, &[9, 8, 7, 6, 5], Mode::Feedback);
+        assert_eq!(amps.run(0), Some(139_629_729));
+        assert!(amps.history().iter().all(|signals| signals.len() == 5));
+    }
+    #[test]
+    fn topology_chain_and_ring() {
+        let amp_code: Vec<i64> = fs::read_to_string("input/day7")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let chain = "
+            amp a 4
+            amp b 1
+            amp c 0
+            amp d 3
+            amp e 2
+            in -> a
+            a -> b
+            b -> c
+            c -> d
+            d -> e
+            e -> out # thrusters
+        ";
+        let topology = Topology::parse(chain).unwrap();
+        let mut amps =
+            AmplifierArray::new(&amp_code, &[4, 1, 0, 3, 2], Mode::Single);
+        assert_eq!(topology.run(&amp_code, &[0]), vec![amps.run(0).unwrap()]);
+        let code = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4,
+            27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let ring = chain.replace("e -> out", "e -> a, out");
+        let ring = Topology::parse(&ring.replace(" 4\n", " 9\n")).unwrap();
+        let ring = ring.run(&code, &[0]);
+        let mut amps =
+            AmplifierArray::new(&code, &[9, 1, 0, 3, 2], Mode::Feedback);
+        assert_eq!(ring.last().cloned(), amps.run(0));
+        assert_eq!(&ring, amps.history().last().unwrap());
+    }
+    #[test]
+    fn topology_fan_out_and_in() {
+        // adds its phase to every signal while the signal is positive
+        let code = compile(
+            "
+            fn main() {
+                let phase = input();
+                while 1 {
+                    let signal = input();
+                    if signal > 0 {
+                        output(signal + phase);
+                    }
+                }
+            }
+        ",
+        )
+        .unwrap();
+        let fan = "
+            amp a 1
+            amp b 10
+            amp sum 100
+            in -> a, b
+            a -> sum
+            b -> sum
+            sum -> out
+        ";
+        let topology = Topology::parse(fan).unwrap();
+        assert_eq!(topology.run(&code, &[1, 2]), vec![102, 103, 111, 112]);
+        // two loops through a, which stops passing signals on once they
+        // drop to zero
+        let loops = "
+            amp a 0
+            amp b -3
+            amp c -5
+            in -> a
+            a -> b, c
+            b -> a, out
+            c -> a, out
+        ";
+        let topology = Topology::parse(loops).unwrap();
+        assert_eq!(
+            topology.run(&code, &[9]),
+            vec![6, 4, 3, 1, 1, -1, 0, -2, -2, -2, -4, -4]
+        );
+    }
+    #[test]
+    fn topology_errors() {
+        let bad = "
+            amp a 0
+            amp a 1
+            amp in 2
+            amp b x
+            amp c 3
+            amp d 4
+            in -> a
+            a -> missing, out
+            out -> a
+            c -> d
+            d -> c
+            a => b
+        ";
+        assert_eq!(
+            Topology::parse(bad),
+            Err([
+                "line 3: 'a' is declared twice",
+                "line 4: 'in' is reserved",
+                "line 5: bad phase 'x'",
+                "line 13: can't read 'a => b'",
+                "line 9: edge to or from unknown amp 'missing'",
+                "line 10: 'out' can't send signals",
+                "amp 'c' can't be reached from 'in'",
+                "amp 'd' can't be reached from 'in'",
+            ]
+            .join("\n"))
+        );
+    }
+    #[test]
+    fn day7_merged_coverage() {
+        let amp_code: Vec<i64> = fs::read_to_string("input/day7")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let mut merged = Coverage::default();
+        let mut single_run = Vec::new();
+        for set in (0..5).permutations(5) {
             let mut input = 0;
-
-            while let Some(output) = run_program_chain(
-                input,
-                &mut [
-                    &mut prog_a,
-                    &mut prog_b,
-                    &mut prog_c,
-                    &mut prog_d,
-                    &mut prog_e,
-                ],
-            ) {
-                input = output;
-                max_out = cmp::max(output, max_out);
+            for phase in set {
+                let mut prog = Program::new(amp_code.clone());
+                prog.collect_coverage();
+                prog.run_input(Some(phase));
+                input = prog.run_input(Some(input)).unwrap();
+                let coverage = prog.coverage().unwrap();
+                single_run.push(coverage.never_ran(&amp_code).len());
+                merged.merge(coverage);
             }
         }
-        assert_eq!(max_out, 17_956_613);
+        let never_ran = merged.never_ran(&amp_code).len();
+        assert!(single_run.iter().all(|&n| n > never_ran));
+    }
+    #[test]
+    fn day7_best_phases() {
+        let amp_code: Vec<i64> = fs::read_to_string("input/day7")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let part1 =
+            best_phases(&amp_code, &[0, 1, 2, 3, 4], 5, Mode::Single, 5);
+        assert_eq!(part1[0].signal, 914_828);
+        assert_eq!(part1.len(), 5);
+        assert!(part1.windows(2).all(|w| w[0].signal >= w[1].signal));
+        let candidates = [5, 6, 7, 8, 9];
+        let part2 = best_phases(&amp_code, &candidates, 5, Mode::Feedback, 3);
+        assert_eq!(part2[0].signal, 17_956_613);
+        assert_eq!(
+            part2,
+            best_phases_brute_force(
+                &amp_code,
+                &candidates,
+                5,
+                Mode::Feedback,
+                3
+            )
+        );
+        // fewer amplifiers than phases to choose from
+        let candidates = [0, 1, 2, 3, 4];
+        for count in 1..4 {
+            assert_eq!(
+                best_phases(&amp_code, &candidates, count, Mode::Single, 4),
+                best_phases_brute_force(
+                    &amp_code,
+                    &candidates,
+                    count,
+                    Mode::Single,
+                    4
+                )
+            );
+        }
+    }
+    #[test]
+    #[ignore]
+    fn phase_search_benchmark() {
+        let amp_code: Vec<i64> = fs::read_to_string("input/day7")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let searches = [
+            ("single 5 of 5", Mode::Single, (0..5).collect::<Vec<_>>(), 5),
+            ("single 6 of 8", Mode::Single, (0..8).collect(), 6),
+            ("feedback 5 of 5", Mode::Feedback, (5..10).collect(), 5),
+        ];
+        for (name, mode, candidates, count) in &searches {
+            let start = Instant::now();
+            let fast = best_phases(&amp_code, candidates, *count, *mode, 3);
+            let fast_time = start.elapsed();
+            let start = Instant::now();
+            let slow = best_phases_brute_force(
+                &amp_code, candidates, *count, *mode, 3,
+            );
+            let slow_time = start.elapsed();
+            assert_eq!(fast, slow);
+            println!(
+                "{:<16} best {:>10} shared {:>10?} brute force {:>10?}",
+                name, fast[0].signal, fast_time, slow_time
+            );
+        }
     }
 }
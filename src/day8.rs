@@ -6,7 +6,7 @@ fn get_layers(input: &[u8], width: usize, height: usize) -> Vec<Vec<u8>> {
     }
     layers
 }
-fn print_layers(layers: &[Vec<u8>], width: usize, height: usize) {
+fn compose(layers: &[Vec<u8>], width: usize, height: usize) -> Vec<u8> {
     let mut image = vec![2u8; width * height];
     let mut head = 0;
     while head < image.len() {
@@ -18,20 +18,26 @@ fn print_layers(layers: &[Vec<u8>], width: usize, height: usize) {
         }
         head += 1;
     }
-    for row in image.chunks(width) {
+    image
+}
+fn print_layers(layers: &[Vec<u8>], width: usize, height: usize) {
+    for row in compose(layers, width, height).chunks(width) {
         println!("{:?}", row);
     }
 }
 #[cfg(test)]
 mod tests {
+    use super::compose;
     use super::get_layers;
     use super::print_layers;
+    use crate::ocr;
     use std::fs;
     #[test]
     fn example_day8_part2() {
         let input = vec![0, 2, 2, 2, 1, 1, 2, 2, 2, 2, 1, 2, 0, 0, 0, 0];
         let layers = get_layers(&input, 2, 2);
         print_layers(&layers, 2, 2);
+        assert_eq!(compose(&layers, 2, 2), vec![0, 1, 1, 0]);
     }
     #[test]
     fn day_8_part1_and_2() {
@@ -60,7 +66,11 @@ mod tests {
         assert_eq!(count[&2], 128);
 
         print_layers(&layers, 25, 6);
-        // HFYAK
-
+        let image = compose(&layers, 25, 6);
+        let rows: Vec<Vec<bool>> = image
+            .chunks(25)
+            .map(|row| row.iter().map(|&pixel| pixel == 1).collect())
+            .collect();
+        assert_eq!(ocr::read(&rows), Ok("HFYAK".to_string()));
     }
 }
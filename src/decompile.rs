@@ -0,0 +1,481 @@
+use super::int_code::{decode, op_size, Op, Param};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Const(i64),
+    Cell(Param),
+    Bin(Box<Expr>, &'static str, Box<Expr>),
+}
+// One instruction (or a recognised pair) before control flow is structured.
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    Assign(Param, Expr),
+    Input(Param),
+    Output(Expr),
+    AdjustBase(Expr),
+    Call(usize),
+    Return,
+    Jump(Expr),
+    Branch(Expr, Expr),
+    Halt,
+}
+enum Stmt {
+    Line(usize, Line),
+    If { addr: usize, cond: Expr, then: Vec<Stmt>, els: Vec<Stmt> },
+    Loop { addr: usize, body: Vec<Stmt> },
+    DoWhile { addr: usize, body: Vec<Stmt>, cond: Expr },
+}
+impl Stmt {
+    fn addr(&self) -> usize {
+        match self {
+            Stmt::Line(addr, _)
+            | Stmt::If { addr, .. }
+            | Stmt::Loop { addr, .. }
+            | Stmt::DoWhile { addr, .. } => *addr,
+        }
+    }
+}
+
+/// Decompile the code reachable from address 0 into pseudo-code.
+///
+/// Calls are recognised as a return address stored relative to the base
+/// followed by a jump, and returns as a jump to a relative cell. Comparisons
+/// feeding straight into a conditional jump are folded into the condition,
+/// so their temporary cell is assumed dead.
+pub fn decompile(code: &[i64]) -> String {
+    let (ops, calls) = explore(code);
+    let lines = merge_idioms(&ops, &calls);
+    let starts: BTreeSet<usize> =
+        calls.iter().cloned().chain(Some(0)).collect();
+    let mut functions: BTreeMap<usize, Vec<(usize, Line)>> = BTreeMap::new();
+    for (addr, line) in lines {
+        if let Some(&start) = starts.range(..=addr).next_back() {
+            functions.entry(start).or_default().push((addr, line));
+        }
+    }
+    let mut out = String::new();
+    for (start, lines) in functions {
+        let index = lines.iter().enumerate().map(|(i, l)| (l.0, i)).collect();
+        let body = structure(&lines, &index, 0, lines.len());
+        let mut labels = HashSet::new();
+        collect_labels(&body, &mut labels);
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        writeln!(out, "fn {}() {{", function_name(start)).unwrap();
+        render(&body, &labels, 1, &mut out);
+        out.push_str("}\n");
+    }
+    out
+}
+
+// Follow every path from address 0, returning the lowered instructions and
+// the targets of recognised calls.
+fn explore(code: &[i64]) -> (BTreeMap<usize, (usize, Line)>, BTreeSet<usize>) {
+    let mut ops = BTreeMap::new();
+    let mut calls = BTreeSet::new();
+    let mut to_visit = vec![0];
+    while let Some(addr) = to_visit.pop() {
+        if ops.contains_key(&addr) {
+            continue;
+        }
+        let op = match decode(code, addr) {
+            Some(op) => op,
+            None => continue,
+        };
+        let next = addr + op_size(&op);
+        let line = match lower(op) {
+            Some(line) => line,
+            None => {
+                to_visit.push(next);
+                continue;
+            }
+        };
+        match &line {
+            Line::Halt | Line::Return | Line::Jump(_) => {}
+            _ => to_visit.push(next),
+        }
+        match &line {
+            Line::Jump(Expr::Const(target)) => {
+                let target = *target as usize;
+                to_visit.push(target);
+                if let Some((_, prev)) =
+                    addr.checked_sub(4).and_then(|a| ops.get(&a))
+                {
+                    if is_return_store(prev, next) {
+                        calls.insert(target);
+                        to_visit.push(next);
+                    }
+                }
+            }
+            Line::Branch(_, Expr::Const(target)) => {
+                to_visit.push(*target as usize)
+            }
+            _ => {}
+        }
+        ops.insert(addr, (next, line));
+    }
+    (ops, calls)
+}
+fn is_return_store(line: &Line, ret: usize) -> bool {
+    match line {
+        Line::Assign(Param::Rel(_), Expr::Const(val)) => *val == ret as i64,
+        _ => false,
+    }
+}
+// Returns None for jumps that can never be taken.
+fn lower(op: Op) -> Option<Line> {
+    let line = match op {
+        Op::Add([a, b, dst]) => Line::Assign(dst, bin(a.into(), "+", b.into())),
+        Op::Mul([a, b, dst]) => Line::Assign(dst, bin(a.into(), "*", b.into())),
+        Op::Less([a, b, dst]) => {
+            Line::Assign(dst, bin(a.into(), "<", b.into()))
+        }
+        Op::Equal([a, b, dst]) => {
+            Line::Assign(dst, bin(a.into(), "==", b.into()))
+        }
+        Op::In(dst) => Line::Input(dst),
+        Op::Out(a) => Line::Output(a.into()),
+        Op::OffsetBase(a) => Line::AdjustBase(a.into()),
+        Op::JumpTrue([cond, target]) => {
+            return jump(truthy(cond.into()), target)
+        }
+        Op::JumpFalse([cond, target]) => {
+            return jump(negate(truthy(cond.into())), target)
+        }
+        Op::Halt => Line::Halt,
+        Op::Ext(..) => unreachable!("decode only yields standard ops"),
+    };
+    Some(line)
+}
+fn jump(cond: Expr, target: Param) -> Option<Line> {
+    match (cond, target) {
+        (Expr::Const(0), _) => None,
+        (Expr::Const(_), Param::Rel(_)) => Some(Line::Return),
+        (Expr::Const(_), target) => Some(Line::Jump(target.into())),
+        (cond, target) => Some(Line::Branch(cond, target.into())),
+    }
+}
+// Turn call sequences into single lines and fold comparisons into the
+// branch that tests them.
+fn merge_idioms(
+    ops: &BTreeMap<usize, (usize, Line)>,
+    calls: &BTreeSet<usize>,
+) -> Vec<(usize, Line)> {
+    let targets: HashSet<usize> =
+        ops.values()
+            .filter_map(|(_, line)| match line {
+                Line::Jump(Expr::Const(t))
+                | Line::Branch(_, Expr::Const(t)) => Some(*t as usize),
+                _ => None,
+            })
+            .collect();
+    let mut lines = Vec::new();
+    let mut iter = ops.iter().peekable();
+    while let Some((&addr, (next, line))) = iter.next() {
+        let following = match iter.peek() {
+            Some((&a, (after, l))) if a == *next && !targets.contains(&a) => {
+                Some((after, l))
+            }
+            _ => None,
+        };
+        let merged = match (line, following) {
+            (store, Some((after, Line::Jump(Expr::Const(t)))))
+                if calls.contains(&(*t as usize))
+                    && is_return_store(store, *after) =>
+            {
+                Some(Line::Call(*t as usize))
+            }
+            (Line::Assign(dst, cmp), Some((_, Line::Branch(cond, target))))
+                if is_comparison(cmp) =>
+            {
+                let cell = Expr::Cell(*dst);
+                if *cond == bin(cell.clone(), "!=", Expr::Const(0)) {
+                    Some(Line::Branch(cmp.clone(), target.clone()))
+                } else if *cond == bin(cell, "==", Expr::Const(0)) {
+                    Some(Line::Branch(negate(cmp.clone()), target.clone()))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        match merged {
+            Some(merged) => {
+                iter.next();
+                lines.push((addr, merged));
+            }
+            None => lines.push((addr, line.clone())),
+        }
+    }
+    lines
+}
+fn structure(
+    lines: &[(usize, Line)],
+    index: &HashMap<usize, usize>,
+    lo: usize,
+    hi: usize,
+) -> Vec<Stmt> {
+    let target_of = |i: usize| match &lines[i].1 {
+        Line::Jump(Expr::Const(t)) | Line::Branch(_, Expr::Const(t)) => {
+            index.get(&(*t as usize)).cloned()
+        }
+        _ => None,
+    };
+    let mut stmts = Vec::new();
+    let mut i = lo;
+    while i < hi {
+        let addr = lines[i].0;
+        if let Some(j) = (i..hi).rev().find(|&j| target_of(j) == Some(i)) {
+            let body = structure(lines, index, i, j);
+            stmts.push(match &lines[j].1 {
+                Line::Branch(cond, _) => {
+                    Stmt::DoWhile { addr, body, cond: cond.clone() }
+                }
+                _ => Stmt::Loop { addr, body },
+            });
+            i = j + 1;
+            continue;
+        }
+        if let (Line::Branch(cond, _), Some(k)) = (&lines[i].1, target_of(i)) {
+            if k > i && k <= hi {
+                let cond = negate(cond.clone());
+                let else_end = target_of(k - 1).filter(|&m| {
+                    k - 1 > i
+                        && m > k
+                        && m <= hi
+                        && matches!(lines[k - 1].1, Line::Jump(_))
+                });
+                if let Some(m) = else_end {
+                    let then = structure(lines, index, i + 1, k - 1);
+                    let els = structure(lines, index, k, m);
+                    stmts.push(Stmt::If { addr, cond, then, els });
+                    i = m;
+                } else {
+                    let then = structure(lines, index, i + 1, k);
+                    stmts.push(Stmt::If { addr, cond, then, els: Vec::new() });
+                    i = k;
+                }
+                continue;
+            }
+        }
+        stmts.push(Stmt::Line(addr, lines[i].1.clone()));
+        i += 1;
+    }
+    stmts
+}
+fn collect_labels(stmts: &[Stmt], labels: &mut HashSet<usize>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Line(_, Line::Jump(Expr::Const(t)))
+            | Stmt::Line(_, Line::Branch(_, Expr::Const(t))) => {
+                labels.insert(*t as usize);
+            }
+            Stmt::Line(..) => {}
+            Stmt::If { then, els, .. } => {
+                collect_labels(then, labels);
+                collect_labels(els, labels);
+            }
+            Stmt::Loop { body, .. } | Stmt::DoWhile { body, .. } => {
+                collect_labels(body, labels)
+            }
+        }
+    }
+}
+fn render(
+    stmts: &[Stmt],
+    labels: &HashSet<usize>,
+    depth: usize,
+    out: &mut String,
+) {
+    let indent = "    ".repeat(depth);
+    for stmt in stmts {
+        if labels.contains(&stmt.addr()) {
+            writeln!(out, "{}L{}:", indent, stmt.addr()).unwrap();
+        }
+        match stmt {
+            Stmt::Line(_, line) => {
+                writeln!(out, "{}{}", indent, render_line(line)).unwrap()
+            }
+            Stmt::If { cond, then, els, .. } => {
+                writeln!(out, "{}if ({}) {{", indent, render_expr(cond))
+                    .unwrap();
+                render(then, labels, depth + 1, out);
+                if !els.is_empty() {
+                    writeln!(out, "{}}} else {{", indent).unwrap();
+                    render(els, labels, depth + 1, out);
+                }
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::Loop { body, .. } => {
+                writeln!(out, "{}loop {{", indent).unwrap();
+                render(body, labels, depth + 1, out);
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::DoWhile { body, cond, .. } => {
+                writeln!(out, "{}do {{", indent).unwrap();
+                render(body, labels, depth + 1, out);
+                writeln!(out, "{}}} while ({})", indent, render_expr(cond))
+                    .unwrap();
+            }
+        }
+    }
+}
+fn render_line(line: &Line) -> String {
+    let goto = |target: &Expr| match target {
+        Expr::Const(t) => format!("goto L{}", t),
+        target => format!("goto *{}", render_expr(target)),
+    };
+    match line {
+        Line::Assign(dst, expr) => {
+            format!("{} = {}", dst, render_expr(expr))
+        }
+        Line::Input(dst) => format!("in {}", dst),
+        Line::Output(expr) => format!("out {}", render_expr(expr)),
+        Line::AdjustBase(expr) => format!("rb += {}", render_expr(expr)),
+        Line::Call(target) => format!("call {}", function_name(*target)),
+        Line::Return => "return".to_string(),
+        Line::Jump(target) => goto(target),
+        Line::Branch(cond, target) => {
+            format!("if ({}) {}", render_expr(cond), goto(target))
+        }
+        Line::Halt => "halt".to_string(),
+    }
+}
+fn render_expr(expr: &Expr) -> String {
+    let operand = |e: &Expr| match e {
+        Expr::Bin(..) => format!("({})", render_expr(e)),
+        e => render_expr(e),
+    };
+    match expr {
+        Expr::Const(val) => val.to_string(),
+        Expr::Cell(param) => param.to_string(),
+        Expr::Bin(a, op, b) => format!("{} {} {}", operand(a), op, operand(b)),
+    }
+}
+fn function_name(start: usize) -> String {
+    if start == 0 {
+        "main".to_string()
+    } else {
+        format!("f{}", start)
+    }
+}
+
+impl From<Param> for Expr {
+    fn from(param: Param) -> Self {
+        match param {
+            Param::Im(val) => Expr::Const(val),
+            cell => Expr::Cell(cell),
+        }
+    }
+}
+// Build a binary expression, folding constants and identities.
+fn bin(a: Expr, op: &'static str, b: Expr) -> Expr {
+    match (a, op, b) {
+        (Expr::Const(x), _, Expr::Const(y)) => Expr::Const(match op {
+            "+" => x + y,
+            "*" => x * y,
+            "<" => (x < y) as i64,
+            ">=" => (x >= y) as i64,
+            "==" => (x == y) as i64,
+            "!=" => (x != y) as i64,
+            _ => unreachable!(),
+        }),
+        (Expr::Const(0), "+", e) | (e, "+", Expr::Const(0)) => e,
+        (Expr::Const(1), "*", e) | (e, "*", Expr::Const(1)) => e,
+        (a, op, b) => Expr::Bin(Box::new(a), op, Box::new(b)),
+    }
+}
+fn is_comparison(expr: &Expr) -> bool {
+    match expr {
+        Expr::Bin(_, op, _) => ["<", ">=", "==", "!="].contains(op),
+        _ => false,
+    }
+}
+// The condition under which a jump-if-true on `expr` is taken.
+fn truthy(expr: Expr) -> Expr {
+    match expr {
+        Expr::Const(val) => Expr::Const((val != 0) as i64),
+        e if is_comparison(&e) => e,
+        e => bin(e, "!=", Expr::Const(0)),
+    }
+}
+fn negate(cond: Expr) -> Expr {
+    match cond {
+        Expr::Const(val) => Expr::Const((val == 0) as i64),
+        Expr::Bin(a, op, b) if ["<", ">=", "==", "!="].contains(&op) => {
+            let op = match op {
+                "<" => ">=",
+                ">=" => "<",
+                "==" => "!=",
+                _ => "==",
+            };
+            Expr::Bin(a, op, b)
+        }
+        e => bin(e, "==", Expr::Const(0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompile;
+    use std::fs;
+    #[test]
+    fn countdown_loop() {
+        let code =
+            vec![1101, 3, 0, 20, 4, 20, 1001, 20, -1, 20, 1005, 20, 4, 99];
+        assert_eq!(
+            decompile(&code),
+            "fn main() {
+    [20] = 3
+    do {
+        out [20]
+        [20] = [20] + -1
+    } while ([20] != 0)
+    halt
+}
+"
+        );
+    }
+    #[test]
+    fn if_else_and_call() {
+        let code = vec![
+            109, 100, 3, 30, 1008, 30, 5, 31, 1006, 31, 16, 104, 1, 1105, 1,
+            18, 104, 0, 21101, 25, 0, 0, 1105, 1, 26, 99, 104, 7, 2105, 1, 0,
+        ];
+        assert_eq!(
+            decompile(&code),
+            "fn main() {
+    rb += 100
+    in [30]
+    if ([30] == 5) {
+        out 1
+    } else {
+        out 0
+    }
+    call f26
+    halt
+}
+
+fn f26() {
+    out 7
+    return
+}
+"
+        );
+    }
+    #[test]
+    fn decompile_day9() {
+        let code: Vec<i64> = fs::read_to_string("input/day9")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let text = decompile(&code);
+        assert!(text.starts_with("fn main() {"));
+        assert!(text.contains("return"));
+    }
+}
@@ -0,0 +1,165 @@
+use super::int_code::{Memory, Program};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Search {
+    Breadth,
+    Depth,
+}
+/// A state reached by the explorer and the inputs that lead to it from the
+/// start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discovery<S, I> {
+    pub state: S,
+    pub inputs: Vec<I>,
+}
+/// Explore every state reachable from `start` by feeding it `choices`.
+/// `advance` is handed the state to fork and returns the state after one
+/// input, or `None` if the choice is a dead end. States with the same `key`
+/// are only expanded once, so the key decides what counts as "the same
+/// place": an observation such as a position, or `state_hash` for the exact
+/// machine. Breadth-first search finds the shortest input sequences.
+/// Discoveries are returned in the order they were found, `start` first.
+pub fn explore<S, I, K>(
+    start: S,
+    choices: &[I],
+    search: Search,
+    mut advance: impl FnMut(&S, &I) -> Option<S>,
+    mut key: impl FnMut(&S) -> K,
+) -> Vec<Discovery<S, I>>
+where
+    I: Clone,
+    K: Hash + Eq,
+{
+    let mut seen = HashSet::new();
+    seen.insert(key(&start));
+    // each node keeps its parent and the input taken from it
+    let mut nodes: Vec<(S, Option<(usize, usize)>)> = vec![(start, None)];
+    let mut frontier = VecDeque::new();
+    frontier.push_back(0);
+    loop {
+        let node = match search {
+            Search::Breadth => frontier.pop_front(),
+            Search::Depth => frontier.pop_back(),
+        };
+        let node = match node {
+            Some(node) => node,
+            None => break,
+        };
+        let mut children = Vec::new();
+        for (choice, input) in choices.iter().enumerate() {
+            if let Some(next) = advance(&nodes[node].0, input) {
+                if seen.insert(key(&next)) {
+                    children.push(nodes.len());
+                    nodes.push((next, Some((node, choice))));
+                }
+            }
+        }
+        // depth first pops the last child, so push them reversed to follow
+        // the choices in order
+        if search == Search::Depth {
+            children.reverse();
+        }
+        frontier.extend(children);
+    }
+    let links: Vec<_> = nodes.iter().map(|(_, link)| *link).collect();
+    nodes
+        .into_iter()
+        .enumerate()
+        .map(|(i, (state, _))| {
+            let mut inputs = Vec::new();
+            let mut at = i;
+            while let Some((parent, choice)) = links[at] {
+                inputs.push(choices[choice].clone());
+                at = parent;
+            }
+            inputs.reverse();
+            Discovery { state, inputs }
+        })
+        .collect()
+}
+/// Hash of everything that decides how a program continues: head, relative
+/// base, pending input and the non-zero memory cells.
+pub fn state_hash<M: Memory>(prog: &Program<M>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (prog.head(), prog.base(), prog.input()).hash(&mut hasher);
+    let memory = prog.memory();
+    for addr in memory.addrs() {
+        let val = memory.load(addr);
+        if val != 0 {
+            (addr, val).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explore, state_hash, Search};
+    use crate::compiler::compile;
+    use crate::int_code::Program;
+    fn counter() -> Program {
+        let source = "
+            fn main() {
+                let total = 0;
+                while 1 {
+                    total = total + input();
+                    output(total);
+                }
+            }
+        ";
+        Program::new(compile(source).unwrap())
+    }
+    // feed one input and return the new total, stopping at 5
+    fn add(state: &(Program, i64), input: &i64) -> Option<(Program, i64)> {
+        let mut prog = state.0.clone();
+        let total = prog.run_input(Some(*input)).unwrap();
+        if total > 5 {
+            None
+        } else {
+            Some((prog, total))
+        }
+    }
+    #[test]
+    fn breadth_first_by_observation() {
+        let found =
+            explore((counter(), 0), &[1, 2], Search::Breadth, add, |s| s.1);
+        let totals: Vec<_> = found.iter().map(|d| d.state.1).collect();
+        assert_eq!(totals, vec![0, 1, 2, 3, 4, 5]);
+        let lengths: Vec<_> = found.iter().map(|d| d.inputs.len()).collect();
+        assert_eq!(lengths, vec![0, 1, 1, 2, 2, 3]);
+        assert_eq!(found[5].inputs, vec![1, 2, 2]);
+    }
+    #[test]
+    fn depth_first_by_observation() {
+        let found =
+            explore((counter(), 0), &[1, 2], Search::Depth, add, |s| s.1);
+        let mut totals: Vec<_> = found.iter().map(|d| d.state.1).collect();
+        totals.sort();
+        assert_eq!(totals, vec![0, 1, 2, 3, 4, 5]);
+        for d in &found {
+            assert_eq!(d.inputs.iter().sum::<i64>(), d.state.1);
+        }
+        // 4 is found below 3 rather than by the shorter [2, 2]
+        assert_eq!(found[4].state.1, 4);
+        assert_eq!(found[4].inputs, vec![1, 2, 1]);
+    }
+    #[test]
+    fn by_state_hash() {
+        // the machine remembers its last input as well as the total, so
+        // exact states tell apart paths that observations merge
+        let found =
+            explore((counter(), 0), &[1, 2], Search::Breadth, add, |s| {
+                state_hash(&s.0)
+            });
+        let mut totals: Vec<_> = found.iter().map(|d| d.state.1).collect();
+        totals.dedup();
+        assert_eq!(totals, vec![0, 1, 2, 3, 4, 5]);
+        assert!(found.len() > 6);
+        for d in &found {
+            assert_eq!(d.inputs.iter().sum::<i64>(), d.state.1);
+        }
+    }
+}
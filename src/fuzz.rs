@@ -0,0 +1,416 @@
+use super::day2;
+use super::day5;
+use super::int_code::{Memory, PagedMemory, Program, Step};
+use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Instruction subsets, from what the day2 machine understands up to the
+/// day9 one. A backend runs every program of its dialect or below.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Dialect {
+    Day2,
+    Day5,
+    Day9,
+}
+/// Shape of the generated programs. Bounding the length and values keeps
+/// them clear of overflow, where backends of different widths legitimately
+/// differ.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub dialect: Dialect,
+    pub max_value: i64,
+    pub max_len: usize,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub output: Vec<i64>,
+    pub memory: Vec<i64>,
+}
+pub struct Backend {
+    pub name: &'static str,
+    pub dialect: Dialect,
+    /// Run a program to completion, feeding `input` to every input
+    /// instruction. Panics are caught and reported as errors.
+    pub run: fn(&[i64], i64) -> Result<Run, String>,
+}
+/// Every interpreter in the crate.
+pub fn backends() -> Vec<Backend> {
+    vec![
+        Backend { name: "day2", dialect: Dialect::Day2, run: run_day2 },
+        Backend { name: "day5", dialect: Dialect::Day5, run: run_day5 },
+        Backend { name: "int_code", dialect: Dialect::Day9, run: run_flat },
+        Backend {
+            name: "int_code paged",
+            dialect: Dialect::Day9,
+            run: run_paged,
+        },
+    ]
+}
+
+/// A generated program. Arguments refer to data cells and instructions by
+/// index, so instructions can be removed while minimising.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub insts: Vec<Inst>,
+    pub data: Vec<i64>,
+    pub input: i64,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub op_code: i64,
+    pub args: Vec<Arg>,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    /// Position mode reference to a data cell.
+    Data(usize),
+    Imm(i64),
+    /// Immediate address of an instruction, or of the final halt.
+    Label(usize),
+    /// Relative mode reference to a data cell, offset by the relative base
+    /// at that instruction.
+    Rel(usize),
+}
+impl Case {
+    /// Lay out the instructions, a halt and then the data cells.
+    pub fn assemble(&self) -> Vec<i64> {
+        let mut starts = Vec::new();
+        let mut addr = 0;
+        for inst in &self.insts {
+            starts.push(addr);
+            addr += 1 + inst.args.len();
+        }
+        starts.push(addr);
+        let data_start = addr + 1;
+        let mut code = Vec::new();
+        let mut base = 0;
+        for inst in &self.insts {
+            let modes: i64 = inst
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| {
+                    let mode = match arg {
+                        Arg::Data(_) => 0,
+                        Arg::Imm(_) | Arg::Label(_) => 1,
+                        Arg::Rel(_) => 2,
+                    };
+                    mode * 10_i64.pow(i as u32 + 2)
+                })
+                .sum();
+            code.push(inst.op_code + modes);
+            code.extend(inst.args.iter().map(|arg| match arg {
+                Arg::Data(cell) => (data_start + cell) as i64,
+                Arg::Imm(val) => *val,
+                Arg::Label(inst) => starts[*inst] as i64,
+                Arg::Rel(cell) => (data_start + cell) as i64 - base,
+            }));
+            if let (9, [Arg::Imm(val)]) = (inst.op_code, &inst.args[..]) {
+                base += val;
+            }
+        }
+        code.push(99);
+        code.extend(&self.data);
+        code
+    }
+}
+#[derive(Debug)]
+pub struct Mismatch {
+    pub case: Case,
+    pub results: Vec<(&'static str, Result<Run, String>)>,
+}
+
+/// Run `iterations` random programs on every backend that supports their
+/// dialect. The first program they disagree on is minimised and returned
+/// with each backend's result.
+pub fn fuzz(
+    backends: &[Backend],
+    config: &Config,
+    seed: u64,
+    iterations: usize,
+) -> Option<Mismatch> {
+    let backends: Vec<&Backend> =
+        backends.iter().filter(|b| b.dialect >= config.dialect).collect();
+    let mut rng = Rng(seed);
+    for _ in 0..iterations {
+        let case = generate(&mut rng, config);
+        if disagree(&case, &backends) {
+            let case = minimize(case, &backends);
+            let results = run_all(&case, &backends);
+            return Some(Mismatch { case, results });
+        }
+    }
+    None
+}
+/// Shrink a disagreeing case by removing instructions and moving values
+/// towards zero for as long as the backends still disagree.
+pub fn minimize(mut case: Case, backends: &[&Backend]) -> Case {
+    loop {
+        let mut candidates = Vec::new();
+        for i in 0..case.insts.len() {
+            candidates.push(remove_inst(&case, i));
+        }
+        for (i, inst) in case.insts.iter().enumerate() {
+            for (j, arg) in inst.args.iter().enumerate() {
+                if let Arg::Imm(val) = arg {
+                    for smaller in shrink(*val) {
+                        let mut candidate = case.clone();
+                        candidate.insts[i].args[j] = Arg::Imm(smaller);
+                        candidates.push(candidate);
+                    }
+                }
+            }
+        }
+        for (i, &val) in case.data.iter().enumerate() {
+            for smaller in shrink(val) {
+                let mut candidate = case.clone();
+                candidate.data[i] = smaller;
+                candidates.push(candidate);
+            }
+        }
+        for smaller in shrink(case.input) {
+            candidates.push(Case { input: smaller, ..case.clone() });
+        }
+        match candidates.into_iter().find(|c| disagree(c, backends)) {
+            Some(smaller) => case = smaller,
+            None => return case,
+        }
+    }
+}
+fn remove_inst(case: &Case, removed: usize) -> Case {
+    let mut case = case.clone();
+    case.insts.remove(removed);
+    for inst in &mut case.insts {
+        for arg in &mut inst.args {
+            if let Arg::Label(target) = arg {
+                if *target > removed {
+                    *target -= 1;
+                }
+            }
+        }
+    }
+    case
+}
+fn shrink(val: i64) -> Vec<i64> {
+    let mut smaller = Vec::new();
+    if val != 0 {
+        smaller.push(0);
+    }
+    if val.abs() > 1 {
+        smaller.push(1);
+    }
+    if val.abs() > 2 {
+        smaller.push(val / 2);
+    }
+    smaller
+}
+fn run_all(
+    case: &Case,
+    backends: &[&Backend],
+) -> Vec<(&'static str, Result<Run, String>)> {
+    let code = case.assemble();
+    backends.iter().map(|b| (b.name, (b.run)(&code, case.input))).collect()
+}
+// Panics count as agreeing with each other whatever the message.
+fn disagree(case: &Case, backends: &[&Backend]) -> bool {
+    let results = run_all(case, backends);
+    results.windows(2).any(|pair| match (&pair[0].1, &pair[1].1) {
+        (Ok(a), Ok(b)) => a != b,
+        (Err(_), Err(_)) => false,
+        _ => true,
+    })
+}
+
+// Jumps only go forward and writes only go to data, so every generated
+// program halts. Jumps never skip a relative base adjustment, which keeps
+// the base at each instruction known when assembling.
+fn generate(rng: &mut Rng, config: &Config) -> Case {
+    let Config { dialect, max_value, max_len } = *config;
+    let data_len = 1 + rng.below(8);
+    let len = 1 + rng.below(max_len);
+    let min_value = if dialect == Dialect::Day2 { 0 } else { -max_value };
+    let ops: &[i64] = match dialect {
+        Dialect::Day2 => &[1, 2],
+        Dialect::Day5 => &[1, 2, 3, 4, 5, 6, 7, 8],
+        Dialect::Day9 => &[1, 2, 3, 4, 5, 6, 7, 8, 9],
+    };
+    let modes = if dialect == Dialect::Day9 { 3 } else { 2 };
+    let read = |rng: &mut Rng| {
+        if dialect == Dialect::Day2 {
+            return Arg::Data(rng.below(data_len));
+        }
+        match rng.below(modes) {
+            0 => Arg::Data(rng.below(data_len)),
+            1 => Arg::Imm(rng.range(min_value, max_value)),
+            _ => Arg::Rel(rng.below(data_len)),
+        }
+    };
+    let write = |rng: &mut Rng| {
+        if dialect == Dialect::Day9 && rng.below(2) == 0 {
+            Arg::Rel(rng.below(data_len))
+        } else {
+            Arg::Data(rng.below(data_len))
+        }
+    };
+    let mut insts = Vec::new();
+    for i in 0..len {
+        let op_code = ops[rng.below(ops.len())];
+        let args = match op_code {
+            1 | 2 | 7 | 8 => vec![read(rng), read(rng), write(rng)],
+            3 => vec![write(rng)],
+            4 => vec![read(rng)],
+            9 => vec![Arg::Imm(rng.range(-max_value, max_value))],
+            _ => vec![read(rng), Arg::Label(i + 1 + rng.below(len - i))],
+        };
+        insts.push(Inst { op_code, args });
+    }
+    for i in 0..len {
+        let adjust = (i + 1..len).find(|&j| insts[j].op_code == 9);
+        if let (Some(adjust), Some(Arg::Label(target))) =
+            (adjust, insts[i].args.get_mut(1))
+        {
+            *target = (*target).min(adjust);
+        }
+    }
+    let data = (0..data_len).map(|_| rng.range(min_value, max_value)).collect();
+    Case { insts, data, input: rng.range(min_value, max_value) }
+}
+// splitmix64, enough for reproducible test programs
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next() % (hi - lo + 1) as u64) as i64
+    }
+}
+
+const STEP_LIMIT: u64 = 100_000;
+fn catch<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|err| {
+        err.downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| err.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panic".to_string())
+    })
+}
+fn run_day2(code: &[i64], _input: i64) -> Result<Run, String> {
+    let code = code
+        .iter()
+        .map(|&v| usize::try_from(v).map_err(|_| format!("bad value {}", v)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let memory = catch(|| day2::run_program(code))?;
+    Ok(Run {
+        output: Vec::new(),
+        memory: memory.into_iter().map(|v| v as i64).collect(),
+    })
+}
+fn run_day5(code: &[i64], input: i64) -> Result<Run, String> {
+    let narrow =
+        |v: i64| i32::try_from(v).map_err(|_| format!("bad value {}", v));
+    let mut code =
+        code.iter().cloned().map(narrow).collect::<Result<Vec<_>, _>>()?;
+    let input = narrow(input)?;
+    let output = catch(|| day5::run_program(&mut code, input))?;
+    Ok(Run {
+        output: output.into_iter().map(i64::from).collect(),
+        memory: code.into_iter().map(i64::from).collect(),
+    })
+}
+fn run_flat(code: &[i64], input: i64) -> Result<Run, String> {
+    run_int_code(Program::new(code.to_vec()), code.len(), input)
+}
+fn run_paged(code: &[i64], input: i64) -> Result<Run, String> {
+    let prog = Program::with_memory(PagedMemory::new(code));
+    run_int_code(prog, code.len(), input)
+}
+fn run_int_code<M: Memory>(
+    mut prog: Program<M>,
+    len: usize,
+    input: i64,
+) -> Result<Run, String> {
+    let mut output = Vec::new();
+    catch(|| loop {
+        match prog.step() {
+            Step::Ran => {}
+            Step::Output(out) => output.push(out),
+            Step::WaitInput => prog.set_input(Some(input)),
+            Step::Halt => return Ok(()),
+        }
+        if prog.steps() > STEP_LIMIT {
+            return Err("step limit reached".to_string());
+        }
+    })??;
+    Ok(Run { output, memory: (0..len).map(|addr| prog.read(addr)).collect() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backends, fuzz, Arg, Backend, Case, Config, Dialect, Inst};
+    #[test]
+    fn assemble() {
+        let case = Case {
+            insts: vec![
+                Inst { op_code: 5, args: vec![Arg::Data(1), Arg::Label(2)] },
+                Inst { op_code: 4, args: vec![Arg::Imm(-3)] },
+            ],
+            data: vec![7, 8],
+            input: 0,
+        };
+        assert_eq!(case.assemble(), vec![1005, 7, 5, 104, -3, 99, 7, 8]);
+        let case = Case {
+            insts: vec![
+                Inst { op_code: 9, args: vec![Arg::Imm(-4)] },
+                Inst { op_code: 3, args: vec![Arg::Rel(1)] },
+            ],
+            data: vec![7, 8],
+            input: 0,
+        };
+        assert_eq!(case.assemble(), vec![109, -4, 203, 10, 99, 7, 8]);
+    }
+    #[test]
+    fn backends_agree() {
+        let config = |dialect, max_value, max_len| Config {
+            dialect,
+            max_value,
+            max_len,
+        };
+        let all = backends();
+        assert!(fuzz(&all, &config(Dialect::Day2, 10, 3), 1, 500).is_none());
+        assert!(fuzz(&all, &config(Dialect::Day5, 10, 3), 2, 500).is_none());
+        let wide: Vec<Backend> =
+            backends().into_iter().filter(|b| b.name != "day5").collect();
+        let day2 = config(Dialect::Day2, 100, 3);
+        assert!(fuzz(&wide, &day2, 3, 500).is_none());
+        let int_code: Vec<Backend> = backends()
+            .into_iter()
+            .filter(|b| b.name.starts_with("int_code"))
+            .collect();
+        let large = config(Dialect::Day5, 1_000_000, 12);
+        assert!(fuzz(&int_code, &large, 4, 500).is_none());
+        let relative = config(Dialect::Day9, 1_000, 12);
+        assert!(fuzz(&all, &relative, 6, 500).is_none());
+    }
+    #[test]
+    fn finds_and_minimises_day5_overflow() {
+        let config = Config {
+            dialect: Dialect::Day5,
+            max_value: 1_000_000,
+            max_len: 12,
+        };
+        let mismatch = fuzz(&backends(), &config, 5, 500).unwrap();
+        assert_eq!(mismatch.case.insts.len(), 1);
+        assert_eq!(mismatch.case.insts[0].op_code, 2);
+        let (name, day5) = &mismatch.results[0];
+        assert_eq!(*name, "day5");
+        assert_ne!(day5, &mismatch.results[1].1);
+    }
+}
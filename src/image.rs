@@ -0,0 +1,257 @@
+//! Pictures of sparse grids such as the day 11 hull, the day 13 screen or the
+//! day 15 map, as PPM, PNG or SVG.
+use std::fmt::Write;
+use std::fs;
+use std::io;
+
+pub type Rgb = [u8; 3];
+
+pub struct Style {
+    // pixels per cell side
+    pub scale: usize,
+    pub background: Rgb,
+    // draw larger y higher up, as for the day 11 and 15 maps
+    pub y_up: bool,
+}
+impl Default for Style {
+    fn default() -> Self {
+        Style { scale: 1, background: [0, 0, 0], y_up: false }
+    }
+}
+/// A dense picture of the cells' bounding box, one color per cell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    scale: usize,
+    background: Rgb,
+    cells: Vec<Option<Rgb>>,
+}
+impl Image {
+    /// Color every cell with `palette`. Cells it returns None for are left
+    /// as background.
+    pub fn from_cells<T>(
+        cells: impl IntoIterator<Item = ((i64, i64), T)>,
+        palette: impl Fn(&T) -> Option<Rgb>,
+        style: &Style,
+    ) -> Image {
+        let cells: Vec<_> = cells.into_iter().collect();
+        let xs = || cells.iter().map(|&((x, _), _)| x);
+        let ys = || cells.iter().map(|&((_, y), _)| y);
+        let (x0, x1) = (xs().min().unwrap_or(0), xs().max().unwrap_or(-1));
+        let (y0, y1) = (ys().min().unwrap_or(0), ys().max().unwrap_or(-1));
+        let width = (x1 - x0 + 1) as usize;
+        let height = (y1 - y0 + 1) as usize;
+        let mut image = Image {
+            width,
+            height,
+            scale: style.scale,
+            background: style.background,
+            cells: vec![None; width * height],
+        };
+        for ((x, y), cell) in &cells {
+            let row = if style.y_up { y1 - y } else { y - y0 };
+            image.cells[row as usize * width + (x - x0) as usize] =
+                palette(cell);
+        }
+        image
+    }
+    fn pixel(&self, x: usize, y: usize) -> Rgb {
+        let cell = self.cells[y / self.scale * self.width + x / self.scale];
+        cell.unwrap_or(self.background)
+    }
+    // rows of scaled pixels, top first
+    fn rows(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let (width, height) =
+            (self.width * self.scale, self.height * self.scale);
+        (0..height)
+            .map(move |y| (0..width).flat_map(|x| self.pixel(x, y)).collect())
+    }
+    /// Binary PPM (P6).
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!(
+            "P6\n{} {}\n255\n",
+            self.width * self.scale,
+            self.height * self.scale
+        )
+        .into_bytes();
+        for row in self.rows() {
+            out.extend(row);
+        }
+        out
+    }
+    /// 8-bit RGB PNG. The image data is stored without compression, which
+    /// keeps the encoder small and is fine for puzzle-sized pictures.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for row in self.rows() {
+            // filter type None
+            raw.push(0);
+            raw.extend(row);
+        }
+        let mut header = Vec::new();
+        header.extend(&((self.width * self.scale) as u32).to_be_bytes());
+        header.extend(&((self.height * self.scale) as u32).to_be_bytes());
+        // bit depth 8, color type RGB, default compression, filter, no
+        // interlace
+        header.extend(&[8, 2, 0, 0, 0]);
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut out, b"IHDR", &header);
+        png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+    /// SVG with a rectangle per colored cell.
+    pub fn to_svg(&self) -> String {
+        let (width, height) =
+            (self.width * self.scale, self.height * self.scale);
+        let hex = |[r, g, b]: Rgb| format!("#{:02x}{:02x}{:02x}", r, g, b);
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" \
+             height=\"{}\">\n<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+            width,
+            height,
+            width,
+            height,
+            hex(self.background)
+        );
+        for (i, cell) in self.cells.iter().enumerate() {
+            if let Some(color) = cell {
+                writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                     fill=\"{}\"/>",
+                    i % self.width * self.scale,
+                    i / self.width * self.scale,
+                    self.scale,
+                    self.scale,
+                    hex(*color)
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+    /// Write the image in the format named by the file extension.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        if path.ends_with(".ppm") {
+            fs::write(path, self.to_ppm())
+        } else if path.ends_with(".png") {
+            fs::write(path, self.to_png())
+        } else if path.ends_with(".svg") {
+            fs::write(path, self.to_svg())
+        } else {
+            let msg = format!("unknown image type: {}", path);
+            Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+        }
+    }
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(&crc.to_be_bytes());
+}
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc =
+                if crc & 1 == 1 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+// zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 0xffff;
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend(&len.to_le_bytes());
+        out.extend(&(!len).to_le_bytes());
+        out.extend(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Image, Style};
+    use std::collections::HashMap;
+    fn checker() -> Image {
+        let mut cells = HashMap::new();
+        cells.insert((5, 7), 'r');
+        cells.insert((6, 8), 'g');
+        cells.insert((6, 7), 'x');
+        Image::from_cells(
+            cells,
+            |c| match c {
+                'r' => Some([255, 0, 0]),
+                'g' => Some([0, 255, 0]),
+                _ => None,
+            },
+            &Style { scale: 2, background: [0, 0, 9], y_up: false },
+        )
+    }
+    #[test]
+    fn ppm() {
+        let r = [255, 0, 0];
+        let g = [0, 255, 0];
+        let b = [0, 0, 9];
+        let mut expected = b"P6\n4 4\n255\n".to_vec();
+        for row in &[[r, r, b, b], [r, r, b, b], [b, b, g, g], [b, b, g, g]] {
+            expected.extend(row.iter().flatten());
+        }
+        assert_eq!(checker().to_ppm(), expected);
+        // larger y drawn higher up
+        let style = Style { y_up: true, ..Style::default() };
+        let image = Image::from_cells(
+            vec![((0, 0), 1), ((0, 1), 2)],
+            |&c| Some([c; 3]),
+            &style,
+        );
+        assert_eq!(&image.to_ppm()[11..], &[2, 2, 2, 1, 1, 1]);
+    }
+    #[test]
+    fn png() {
+        let png = checker().to_png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+        // IHDR: 4x4, 8-bit RGB
+        assert_eq!(&png[16..29], &[0, 0, 0, 4, 0, 0, 0, 4, 8, 2, 0, 0, 0]);
+        // one stored block of 4 rows of a filter byte and 12 color bytes
+        let idat = &png[33 + 8..];
+        assert_eq!(&idat[..7], &[0x78, 0x01, 1, 52, 0, !52, 0xff]);
+        assert_eq!(&idat[7..20], &[0, 255, 0, 0, 255, 0, 0, 0, 0, 9, 0, 0, 9]);
+    }
+    #[test]
+    fn svg() {
+        assert_eq!(
+            checker().to_svg(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"4\" height=\"4\">
+<rect width=\"4\" height=\"4\" fill=\"#000009\"/>
+<rect x=\"0\" y=\"0\" width=\"2\" height=\"2\" fill=\"#ff0000\"/>
+<rect x=\"2\" y=\"2\" width=\"2\" height=\"2\" fill=\"#00ff00\"/>
+</svg>
+"
+        );
+    }
+}
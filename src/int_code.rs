@@ -1,11 +1,16 @@
-#[derive(Debug)]
-enum Param {
+use super::coverage::Coverage;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Param {
     Pos(usize),
     Rel(i64),
     Im(i64),
 }
-#[derive(Debug)]
-enum Op {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
     Add([Param; 3]),
     Mul([Param; 3]),
     In(Param),
@@ -16,16 +21,328 @@ enum Op {
     Equal([Param; 3]),
     OffsetBase(Param),
     Halt,
+    Ext(i64, Vec<Param>),
 }
-pub struct Program {
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Param::Pos(pos) => write!(f, "[{}]", pos),
+            Param::Rel(0) => write!(f, "[rb]"),
+            Param::Rel(rel) if *rel < 0 => write!(f, "[rb-{}]", -rel),
+            Param::Rel(rel) => write!(f, "[rb+{}]", rel),
+            Param::Im(val) => write!(f, "{}", val),
+        }
+    }
+}
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, params): (String, &[Param]) = match self {
+            Op::Add(p) => ("add".into(), p),
+            Op::Mul(p) => ("mul".into(), p),
+            Op::In(p) => ("in".into(), std::slice::from_ref(p)),
+            Op::Out(p) => ("out".into(), std::slice::from_ref(p)),
+            Op::JumpTrue(p) => ("jnz".into(), p),
+            Op::JumpFalse(p) => ("jz".into(), p),
+            Op::Less(p) => ("lt".into(), p),
+            Op::Equal(p) => ("eq".into(), p),
+            Op::OffsetBase(p) => ("arb".into(), std::slice::from_ref(p)),
+            Op::Halt => ("halt".into(), &[]),
+            Op::Ext(op_code, p) => (format!("ext{}", op_code), p),
+        };
+        write!(f, "{}", name)?;
+        for (i, param) in params.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, param)?;
+        }
+        Ok(())
+    }
+}
+/// Handler for a registered opcode. It gets the decoded parameters and may
+/// read and write through the machine; returning a value makes it an output.
+/// Writes must go through `write_param` to be undone by `step_back` and seen
+/// by hooks and self-modification tracking. The head moves past the
+/// instruction unless the handler set it.
+pub type OpHandler<M = Vec<i64>> = fn(&mut Program<M>, &[Param]) -> Option<i64>;
+#[derive(Clone)]
+struct Extension<M> {
+    arity: usize,
+    handler: OpHandler<M>,
+}
+/// Storage for a machine's memory. Cells that were never written read as
+/// zero, so programs don't need padding to use memory past their image.
+pub trait Memory: Clone {
+    fn load(&self, addr: usize) -> i64;
+    fn store(&mut self, addr: usize, val: i64);
+    /// Addresses that may hold a non-zero value, in ascending order.
+    fn addrs(&self) -> Box<dyn Iterator<Item = usize> + '_>;
+}
+/// Flat memory that grows to the highest address written.
+impl Memory for Vec<i64> {
+    fn load(&self, addr: usize) -> i64 {
+        self.get(addr).copied().unwrap_or(0)
+    }
+    fn store(&mut self, addr: usize, val: i64) {
+        if addr >= self.len() {
+            self.resize(addr + 1, 0);
+        }
+        self[addr] = val;
+    }
+    fn addrs(&self) -> Box<dyn Iterator<Item = usize> + '_> {
+        Box::new(0..self.len())
+    }
+}
+const PAGE_SIZE: usize = 256;
+/// Sparse memory only allocating the pages that have been written, for
+/// programs addressing far beyond their image.
+#[derive(Clone, Default)]
+pub struct PagedMemory {
+    pages: HashMap<usize, Box<[i64; PAGE_SIZE]>>,
+}
+impl PagedMemory {
+    pub fn new(image: &[i64]) -> Self {
+        let mut memory = PagedMemory::default();
+        for (addr, &val) in image.iter().enumerate() {
+            memory.store(addr, val);
+        }
+        memory
+    }
+}
+impl From<&[i64]> for PagedMemory {
+    fn from(image: &[i64]) -> Self {
+        PagedMemory::new(image)
+    }
+}
+impl Memory for PagedMemory {
+    fn load(&self, addr: usize) -> i64 {
+        self.pages.get(&(addr / PAGE_SIZE)).map_or(0, |p| p[addr % PAGE_SIZE])
+    }
+    fn store(&mut self, addr: usize, val: i64) {
+        let page = addr / PAGE_SIZE;
+        if val == 0 && !self.pages.contains_key(&page) {
+            return;
+        }
+        let page =
+            self.pages.entry(page).or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        page[addr % PAGE_SIZE] = val;
+    }
+    fn addrs(&self) -> Box<dyn Iterator<Item = usize> + '_> {
+        let mut pages: Vec<usize> = self.pages.keys().cloned().collect();
+        pages.sort();
+        Box::new(
+            pages.into_iter().flat_map(|p| p * PAGE_SIZE..(p + 1) * PAGE_SIZE),
+        )
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelfModKind {
+    /// The written cell was part of an instruction that already ran.
+    Executed,
+    /// The written cell is part of the instruction that runs next.
+    Pending,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfModification {
+    /// Address of the instruction that did the write.
+    pub at: usize,
+    pub addr: usize,
+    pub old: i64,
+    pub new: i64,
+    pub kind: SelfModKind,
+}
+#[derive(Default, Clone)]
+struct SelfModTracker {
+    executed: HashSet<usize>,
+    last_write: Option<(usize, i64, i64)>,
+    events: Vec<SelfModification>,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellChange {
+    pub addr: usize,
+    pub before: i64,
+    pub after: i64,
+}
+#[derive(Debug, Default, PartialEq)]
+pub struct StateDiff {
+    pub head: Option<(usize, usize)>,
+    pub base: Option<(usize, usize)>,
+    pub cells: Vec<CellChange>,
+}
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none() && self.base.is_none() && self.cells.is_empty()
+    }
+}
+/// Callbacks from inside the interpreter. Every method does nothing by
+/// default, so a hook only implements what it watches.
+pub trait Hook {
+    /// Before the instruction at `addr` executes, including a halt.
+    fn instruction(&mut self, _addr: usize, _op: &Op) {}
+    /// An instruction stored `new` at `addr`, which held `old`.
+    fn write(&mut self, _addr: usize, _old: i64, _new: i64) {}
+    /// The relative base was adjusted from `old` to `new`.
+    fn base(&mut self, _old: usize, _new: usize) {}
+}
+/// A shared hook, so the caller can keep a handle to read results back.
+impl<H: Hook> Hook for Arc<Mutex<H>> {
+    fn instruction(&mut self, addr: usize, op: &Op) {
+        self.lock().unwrap().instruction(addr, op);
+    }
+    fn write(&mut self, addr: usize, old: i64, new: i64) {
+        self.lock().unwrap().write(addr, old, new);
+    }
+    fn base(&mut self, old: usize, new: usize) {
+        self.lock().unwrap().base(old, new);
+    }
+}
+// The installed hooks. They belong to one run, so clones of a program
+// start without any.
+#[derive(Default)]
+struct Hooks(Vec<Box<dyn Hook + Send>>);
+impl Clone for Hooks {
+    fn clone(&self) -> Self {
+        Hooks::default()
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Ran,
+    Output(i64),
+    WaitInput,
+    Halt,
+}
+/// Why a batch of instructions stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The predicate held, or the instruction budget ran out.
+    Done,
+    WaitInput,
+    Halt,
+}
+// What an executed instruction changed, enough to put it back.
+#[derive(Default, Clone)]
+struct Undo {
     head: usize,
-    code: Vec<i64>,
     base: usize,
     input: Option<i64>,
+    writes: Vec<(usize, i64)>,
+}
+#[derive(Clone)]
+pub struct Program<M = Vec<i64>> {
+    head: usize,
+    code: M,
+    base: usize,
+    input: Option<i64>,
+    self_mod: Option<SelfModTracker>,
+    extensions: HashMap<i64, Extension<M>>,
+    steps: u64,
+    history: Option<Vec<Undo>>,
+    coverage: Option<Coverage>,
+    hooks: Hooks,
+    // where the machine halted, so repeated halts are only counted once
+    halted: Option<usize>,
 }
 impl Program {
     pub fn new(code: Vec<i64>) -> Self {
-        Program { head: 0, code, base: 0, input: None }
+        Program::with_memory(code)
+    }
+}
+impl<M: Memory> Program<M> {
+    pub fn with_memory(code: M) -> Self {
+        Program {
+            head: 0,
+            code,
+            base: 0,
+            input: None,
+            self_mod: None,
+            extensions: HashMap::new(),
+            steps: 0,
+            history: None,
+            coverage: None,
+            hooks: Hooks::default(),
+            halted: None,
+        }
+    }
+    /// Add an opcode outside the standard instruction set. Only unused
+    /// two-digit opcodes can be registered.
+    pub fn register_op(
+        &mut self,
+        op_code: i64,
+        arity: usize,
+        handler: OpHandler<M>,
+    ) {
+        assert!(
+            (1..99).contains(&op_code)
+                && op_len(op_code, |op| self.arity(op)) == 0,
+            "op code ({}) is taken",
+            op_code
+        );
+        self.extensions.insert(op_code, Extension { arity, handler });
+    }
+    /// Start recording writes that land on executed or pending instructions.
+    pub fn track_self_modification(&mut self) {
+        self.self_mod.get_or_insert_with(SelfModTracker::default);
+    }
+    pub fn self_modifications(&self) -> &[SelfModification] {
+        self.self_mod.as_ref().map_or(&[], |t| &t.events)
+    }
+    /// Call `hook` from now on, after any hooks added before it. Pass an
+    /// `Arc<Mutex<_>>` and keep a clone to read results back. Clones of the
+    /// program don't inherit hooks.
+    pub fn add_hook(&mut self, hook: impl Hook + Send + 'static) {
+        self.hooks.0.push(Box::new(hook));
+    }
+    pub fn hooks(&self) -> usize {
+        self.hooks.0.len()
+    }
+    /// Remove every hook, returning them in the order they were added.
+    pub fn take_hooks(&mut self) -> Vec<Box<dyn Hook + Send>> {
+        std::mem::take(&mut self.hooks.0)
+    }
+    pub fn head(&self) -> usize {
+        self.head
+    }
+    pub fn set_head(&mut self, head: usize) {
+        self.head = head;
+        self.halted = None;
+    }
+    pub fn base(&self) -> usize {
+        self.base
+    }
+    pub fn set_base(&mut self, base: usize) {
+        self.base = base;
+    }
+    pub fn memory(&self) -> &M {
+        &self.code
+    }
+    pub fn read(&self, addr: usize) -> i64 {
+        self.code.load(addr)
+    }
+    /// Store `val` at `addr` from outside the program, e.g. to patch it
+    /// before a run. The write isn't part of any instruction, so it isn't
+    /// undone by `step_back` or seen by hooks or self-modification tracking.
+    /// Instructions and opcode handlers write with `write_param`.
+    pub fn write(&mut self, addr: usize, val: i64) {
+        self.code.store(addr, val);
+        self.halted = None;
+    }
+    /// Compare against an earlier (or unrelated) state of a machine.
+    pub fn diff(&self, before: &Program<M>) -> StateDiff {
+        let changed = |b, a| if b != a { Some((b, a)) } else { None };
+        let addrs: BTreeSet<usize> =
+            self.code.addrs().chain(before.code.addrs()).collect();
+        let cells = addrs
+            .into_iter()
+            .filter(|&addr| before.read(addr) != self.read(addr))
+            .map(|addr| CellChange {
+                addr,
+                before: before.read(addr),
+                after: self.read(addr),
+            })
+            .collect();
+        StateDiff {
+            head: changed(before.head, self.head),
+            base: changed(before.base, self.base),
+            cells,
+        }
     }
     pub fn run_input(&mut self, input: Option<i64>) -> Option<i64> {
         self.input = input;
@@ -34,80 +351,334 @@ impl Program {
     pub fn set_input(&mut self, input: Option<i64>) {
         self.input = input;
     }
+    pub fn input(&self) -> Option<i64> {
+        self.input
+    }
     pub fn run(&mut self) -> Option<i64> {
         loop {
-            let op = parse_op(&self.code, self.head);
-            let value = |p: Param| match p {
-                Param::Pos(pos) => self.code[pos],
-                Param::Im(im) => im,
-                Param::Rel(rel) => {
-                    let pos = (self.base as i64 + rel) as usize;
-                    self.code[pos]
-                }
-            };
-            let pos = |p: Param| match p {
-                Param::Pos(pos) => pos,
-                Param::Rel(rel) => (self.base as i64 + rel) as usize,
-                Param::Im(_) => panic!("immediate value invalid as pos"),
-            };
-            match op {
-                Op::Add([p1, p2, p3]) => {
-                    let pos = pos(p3);
-                    self.code[pos] = value(p1) + value(p2);
-                    self.head += 4;
+            match self.step() {
+                Step::Ran => {}
+                Step::Output(out) => return Some(out),
+                Step::WaitInput | Step::Halt => return None,
+            }
+        }
+    }
+    /// Execute until `done` holds, checked after every instruction with the
+    /// machine and the outputs of this batch, which are appended to
+    /// `outputs`. Also stops when input is needed or the program halts.
+    pub fn run_until(
+        &mut self,
+        outputs: &mut Vec<i64>,
+        mut done: impl FnMut(&Self, &[i64]) -> bool,
+    ) -> Stop {
+        let start = outputs.len();
+        loop {
+            match self.step() {
+                Step::Ran => {}
+                Step::Output(out) => outputs.push(out),
+                Step::WaitInput => return Stop::WaitInput,
+                Step::Halt => return Stop::Halt,
+            }
+            if done(self, &outputs[start..]) {
+                return Stop::Done;
+            }
+        }
+    }
+    /// Execute at most `n` instructions, appending outputs to `outputs`.
+    pub fn run_for(&mut self, n: u64, outputs: &mut Vec<i64>) -> Stop {
+        if n == 0 {
+            return Stop::Done;
+        }
+        let end = self.steps + n;
+        self.run_until(outputs, |prog, _| prog.steps >= end)
+    }
+    /// Execute a single instruction. Waiting for input and halting leave the
+    /// machine unchanged.
+    pub fn step(&mut self) -> Step {
+        let op = parse_op(
+            |addr| self.code.load(addr),
+            self.head,
+            |op| self.arity(op),
+        );
+        let at = self.head;
+        if let Op::In(_) = op {
+            if self.input.is_none() {
+                return Step::WaitInput;
+            }
+        }
+        if op == Op::Halt {
+            if self.halted != Some(at) {
+                self.halted = Some(at);
+                for hook in &mut self.hooks.0 {
+                    hook.instruction(at, &op);
                 }
-                Op::Mul([p1, p2, p3]) => {
-                    let pos = pos(p3);
-                    self.code[pos] = value(p1) * value(p2);
-                    self.head += 4;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.hit(at);
                 }
-                Op::In(param) => {
-                    let pos = pos(param);
-                    self.code[pos] = self.input.take()?;
-                    self.head += 2;
+            }
+            return Step::Halt;
+        }
+        for hook in &mut self.hooks.0 {
+            hook.instruction(at, &op);
+        }
+        self.steps += 1;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.hit(at);
+        }
+        if let Some(history) = &mut self.history {
+            history.push(Undo { head: at, base: self.base, ..Undo::default() });
+        }
+        let len = op_len(self.code.load(at), |op| self.arity(op));
+        if let Some(tracker) = &mut self.self_mod {
+            tracker.executed.extend(at..at + len);
+        }
+        let out = match op {
+            Op::Add([p1, p2, p3]) => {
+                let val = self.value(p1) + self.value(p2);
+                self.write_param(p3, val);
+                self.head += 4;
+                None
+            }
+            Op::Mul([p1, p2, p3]) => {
+                let val = self.value(p1) * self.value(p2);
+                self.write_param(p3, val);
+                self.head += 4;
+                None
+            }
+            Op::In(param) => {
+                let val = self.input.take().unwrap();
+                if let Some(undo) =
+                    self.history.as_mut().and_then(|h| h.last_mut())
+                {
+                    undo.input = Some(val);
                 }
-                Op::Out(param) => {
-                    let out = value(param);
-                    self.head += 2;
-                    return Some(out);
+                self.write_param(param, val);
+                self.head += 2;
+                None
+            }
+            Op::Out(param) => {
+                let out = self.value(param);
+                self.head += 2;
+                Some(out)
+            }
+            Op::JumpTrue([p1, p2]) => {
+                let taken = self.value(p1) != 0;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.branch(at, taken);
                 }
-                Op::JumpTrue([p1, p2]) => {
-                    if value(p1) != 0 {
-                        self.head = value(p2) as usize;
-                    } else {
-                        self.head += 3;
-                    }
+                if taken {
+                    self.head = self.value(p2) as usize;
+                } else {
+                    self.head += 3;
                 }
-                Op::JumpFalse([p1, p2]) => {
-                    if value(p1) == 0 {
-                        self.head = value(p2) as usize;
-                    } else {
-                        self.head += 3;
-                    }
+                None
+            }
+            Op::JumpFalse([p1, p2]) => {
+                let taken = self.value(p1) == 0;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.branch(at, taken);
                 }
-                Op::Less([p1, p2, p3]) => {
-                    let pos = pos(p3);
-                    self.code[pos] = if value(p1) < value(p2) { 1 } else { 0 };
-                    self.head += 4;
+                if taken {
+                    self.head = self.value(p2) as usize;
+                } else {
+                    self.head += 3;
                 }
-                Op::Equal([p1, p2, p3]) => {
-                    let pos = pos(p3);
-                    self.code[pos] = if value(p1) == value(p2) { 1 } else { 0 };
-                    self.head += 4;
+                None
+            }
+            Op::Less([p1, p2, p3]) => {
+                let val = if self.value(p1) < self.value(p2) { 1 } else { 0 };
+                self.write_param(p3, val);
+                self.head += 4;
+                None
+            }
+            Op::Equal([p1, p2, p3]) => {
+                let val = if self.value(p1) == self.value(p2) { 1 } else { 0 };
+                self.write_param(p3, val);
+                self.head += 4;
+                None
+            }
+            Op::OffsetBase(param) => {
+                let offset = self.value(param);
+                let old = self.base;
+                self.base = (self.base as i64 + offset) as usize;
+                for hook in &mut self.hooks.0 {
+                    hook.base(old, self.base);
                 }
-                Op::OffsetBase(param) => {
-                    let offset = value(param);
-                    self.base = (self.base as i64 + offset) as usize;
-                    self.head += 2;
+                self.head += 2;
+                None
+            }
+            Op::Halt => unreachable!(),
+            Op::Ext(op_code, params) => {
+                let handler = self.extensions[&op_code].handler;
+                let out = handler(self, &params);
+                if self.head == at {
+                    self.head += 1 + params.len();
                 }
-                Op::Halt => return None,
+                out
+            }
+        };
+        if self.self_mod.is_some() {
+            self.record_self_modification(at);
+        }
+        match out {
+            Some(out) => Step::Output(out),
+            None => Step::Ran,
+        }
+    }
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    /// Start counting executed instructions and branch directions.
+    pub fn collect_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::default);
+    }
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+    /// Start keeping an undo log so execution can be stepped backwards.
+    pub fn record_history(&mut self) {
+        self.history.get_or_insert_with(Vec::new);
+    }
+    /// Undo the last executed instruction, restoring memory, head, base and
+    /// any consumed input. Returns false when there is nothing to undo.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history.as_mut().and_then(|h| h.pop()) {
+            Some(undo) => undo,
+            None => return false,
+        };
+        for &(addr, old) in undo.writes.iter().rev() {
+            self.code.store(addr, old);
+        }
+        if undo.input.is_some() {
+            self.input = undo.input;
+        }
+        self.head = undo.head;
+        self.base = undo.base;
+        self.steps -= 1;
+        self.halted = None;
+        true
+    }
+    /// Step backwards until the instruction that last wrote `addr` is the
+    /// next to execute. Returns false if no recorded instruction wrote it,
+    /// in which case the machine is rewound as far as the log goes.
+    pub fn reverse_continue(&mut self, addr: usize) -> bool {
+        loop {
+            let wrote = match self.history.as_ref().and_then(|h| h.last()) {
+                Some(undo) => undo.writes.iter().any(|&(a, _)| a == addr),
+                None => return false,
+            };
+            self.step_back();
+            if wrote {
+                return true;
             }
         }
     }
+    /// Step backwards until `steps` instructions have been executed.
+    pub fn rewind_to(&mut self, steps: u64) -> bool {
+        while self.steps > steps {
+            if !self.step_back() {
+                return false;
+            }
+        }
+        self.steps == steps
+    }
+    pub fn value(&self, p: Param) -> i64 {
+        match p {
+            Param::Im(im) => im,
+            Param::Pos(_) | Param::Rel(_) => self.code.load(self.pos(p)),
+        }
+    }
+    // Negative addresses are invalid in every memory backend. A negative
+    // position parameter was decoded as a wrapped usize, so cast it back.
+    fn pos(&self, p: Param) -> usize {
+        let addr = match p {
+            Param::Pos(pos) => pos as i64,
+            Param::Rel(rel) => self.base as i64 + rel,
+            Param::Im(_) => panic!("immediate value invalid as pos"),
+        };
+        usize::try_from(addr).unwrap_or_else(|_| {
+            panic!("negative address ({}) at ({})", addr, self.head)
+        })
+    }
+    pub fn write_param(&mut self, p: Param, val: i64) {
+        let pos = self.pos(p);
+        let old = self.code.load(pos);
+        self.code.store(pos, val);
+        if let Some(tracker) = &mut self.self_mod {
+            tracker.last_write = Some((pos, old, val));
+        }
+        if let Some(undo) = self.history.as_mut().and_then(|h| h.last_mut()) {
+            undo.writes.push((pos, old));
+        }
+        for hook in &mut self.hooks.0 {
+            hook.write(pos, old, val);
+        }
+    }
+    fn arity(&self, op_code: i64) -> Option<usize> {
+        self.extensions.get(&op_code).map(|ext| ext.arity)
+    }
+    // Called after the instruction at `at` has finished and `head` points
+    // at the next one, so a write into that instruction counts as pending.
+    // A write to its opcode is pending even if it made the opcode invalid.
+    fn record_self_modification(&mut self, at: usize) {
+        let next = self.head;
+        let write = self.self_mod.as_mut().and_then(|t| t.last_write.take());
+        let (addr, old, new) = match write {
+            Some(write) => write,
+            None => return,
+        };
+        let next_len = op_len(self.code.load(next), |op| self.arity(op));
+        let tracker = self.self_mod.as_mut().unwrap();
+        let kind = if addr == next || (next..next + next_len).contains(&addr) {
+            SelfModKind::Pending
+        } else if tracker.executed.contains(&addr) {
+            SelfModKind::Executed
+        } else {
+            return;
+        };
+        tracker.events.push(SelfModification { at, addr, old, new, kind });
+    }
 }
 
-fn parse_op(code: &[i64], head: usize) -> Op {
-    let op_code = code[head];
+fn op_len(op_code: i64, arity: impl Fn(i64) -> Option<usize>) -> usize {
+    match op_code % 100 {
+        1 | 2 | 7 | 8 => 4,
+        5 | 6 => 3,
+        3 | 4 | 9 => 2,
+        99 => 1,
+        de => arity(de).map_or(0, |arity| 1 + arity),
+    }
+}
+/// Decode the standard instruction at `head` without executing it, for
+/// static analysis. Returns None where the cells don't form a valid
+/// instruction, e.g. in data.
+pub fn decode(code: &[i64], head: usize) -> Option<Op> {
+    let op_code = *code.get(head)?;
+    let len = op_len(op_code, |_| None);
+    if op_code < 0 || len == 0 || head + len > code.len() {
+        return None;
+    }
+    if (0..len as u32 - 1).any(|i| op_code / 100 / 10_i64.pow(i) % 10 > 2) {
+        return None;
+    }
+    Some(parse_op(|addr| code[addr], head, |_| None))
+}
+pub fn op_size(op: &Op) -> usize {
+    match op {
+        Op::Add(_) | Op::Mul(_) | Op::Less(_) | Op::Equal(_) => 4,
+        Op::JumpTrue(_) | Op::JumpFalse(_) => 3,
+        Op::In(_) | Op::Out(_) | Op::OffsetBase(_) => 2,
+        Op::Halt => 1,
+        Op::Ext(_, params) => 1 + params.len(),
+    }
+}
+fn parse_op(
+    read: impl Fn(usize) -> i64,
+    head: usize,
+    arity: impl Fn(i64) -> Option<usize>,
+) -> Op {
+    let op_code = read(head);
     let de = op_code % 100;
     let op_code = op_code / 100;
     let c = op_code % 10;
@@ -122,36 +693,381 @@ fn parse_op(code: &[i64], head: usize) -> Op {
     match de {
         99 => Op::Halt,
         1 => Op::Add([
-            make_param(code[head + 1], c),
-            make_param(code[head + 2], b),
-            make_param(code[head + 3], a),
+            make_param(read(head + 1), c),
+            make_param(read(head + 2), b),
+            make_param(read(head + 3), a),
         ]),
         2 => Op::Mul([
-            make_param(code[head + 1], c),
-            make_param(code[head + 2], b),
-            make_param(code[head + 3], a),
+            make_param(read(head + 1), c),
+            make_param(read(head + 2), b),
+            make_param(read(head + 3), a),
         ]),
-        3 => Op::In(make_param(code[head + 1], c)),
-        4 => Op::Out(make_param(code[head + 1], c)),
+        3 => Op::In(make_param(read(head + 1), c)),
+        4 => Op::Out(make_param(read(head + 1), c)),
         5 => Op::JumpTrue([
-            make_param(code[head + 1], c),
-            make_param(code[head + 2], b),
+            make_param(read(head + 1), c),
+            make_param(read(head + 2), b),
         ]),
         6 => Op::JumpFalse([
-            make_param(code[head + 1], c),
-            make_param(code[head + 2], b),
+            make_param(read(head + 1), c),
+            make_param(read(head + 2), b),
         ]),
         7 => Op::Less([
-            make_param(code[head + 1], c),
-            make_param(code[head + 2], b),
-            make_param(code[head + 3], a),
+            make_param(read(head + 1), c),
+            make_param(read(head + 2), b),
+            make_param(read(head + 3), a),
         ]),
         8 => Op::Equal([
-            make_param(code[head + 1], c),
-            make_param(code[head + 2], b),
-            make_param(code[head + 3], a),
+            make_param(read(head + 1), c),
+            make_param(read(head + 2), b),
+            make_param(read(head + 3), a),
         ]),
-        9 => Op::OffsetBase(make_param(code[head + 1], c)),
-        bad => panic!("bad op code ({}) at ({})", bad, head),
+        9 => Op::OffsetBase(make_param(read(head + 1), c)),
+        de => match arity(de) {
+            Some(arity) => Op::Ext(
+                de,
+                (0..arity)
+                    .map(|i| {
+                        let mode = op_code / 10_i64.pow(i as u32) % 10;
+                        make_param(read(head + 1 + i), mode)
+                    })
+                    .collect(),
+            ),
+            None => panic!("bad op code ({}) at ({})", de, head),
+        },
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::{
+        CellChange, Hook, Memory, Op, PagedMemory, Param, Program, SelfModKind,
+        SelfModification, Step, Stop,
+    };
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+    #[test]
+    fn self_modification() {
+        let mut prog = Program::new(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
+        prog.track_self_modification();
+        assert_eq!(prog.run(), None);
+        assert_eq!(
+            prog.self_modifications(),
+            &[
+                SelfModification {
+                    at: 0,
+                    addr: 4,
+                    old: 99,
+                    new: 2,
+                    kind: SelfModKind::Pending,
+                },
+                SelfModification {
+                    at: 4,
+                    addr: 0,
+                    old: 1,
+                    new: 30,
+                    kind: SelfModKind::Executed,
+                },
+            ]
+        );
+    }
+    #[test]
+    #[should_panic(expected = "bad op code (0) at (4)")]
+    fn self_modification_breaking_next_op() {
+        // overwrite the halt that comes next with an invalid opcode
+        let mut prog = Program::new(vec![1101, 0, 0, 4, 99]);
+        prog.track_self_modification();
+        assert_eq!(prog.step(), Step::Ran);
+        assert_eq!(
+            prog.self_modifications(),
+            &[SelfModification {
+                at: 0,
+                addr: 4,
+                old: 99,
+                new: 0,
+                kind: SelfModKind::Pending,
+            }]
+        );
+        prog.step();
+    }
+    #[test]
+    fn self_modification_untracked() {
+        let mut prog = Program::new(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
+        assert_eq!(prog.run(), None);
+        assert!(prog.self_modifications().is_empty());
+    }
+    #[test]
+    fn memory_access_and_diff() {
+        let mut prog = Program::new(vec![1, 0, 0, 0, 99]);
+        let before = prog.clone();
+        assert!(prog.diff(&before).is_empty());
+        assert_eq!(prog.run(), None);
+        assert_eq!(prog.read(0), 2);
+        assert_eq!(prog.read(100), 0);
+        let diff = prog.diff(&before);
+        assert_eq!(diff.head, Some((0, 4)));
+        assert_eq!(diff.base, None);
+        assert_eq!(
+            diff.cells,
+            vec![CellChange { addr: 0, before: 1, after: 2 }]
+        );
+
+        prog.write(6, 7);
+        prog.set_head(0);
+        prog.set_base(3);
+        assert_eq!(prog.memory(), &[2, 0, 0, 0, 99, 0, 7]);
+        let diff = prog.diff(&before);
+        assert_eq!(diff.head, None);
+        assert_eq!(diff.base, Some((0, 3)));
+        assert_eq!(diff.cells.len(), 2);
+        assert_eq!(diff.cells[1], CellChange { addr: 6, before: 0, after: 7 });
+    }
+    #[test]
+    fn extension_ops() {
+        // 50: output the sum of two params, 51: write twice param 1 to param 2
+        fn print_sum(prog: &mut Program, params: &[Param]) -> Option<i64> {
+            Some(prog.value(params[0]) + prog.value(params[1]))
+        }
+        fn double(prog: &mut Program, params: &[Param]) -> Option<i64> {
+            let val = prog.value(params[0]) * 2;
+            prog.write_param(params[1], val);
+            None
+        }
+        let mut prog = Program::new(vec![151, 21, 8, 1050, 8, 0, 99, 0, 0]);
+        prog.register_op(50, 2, print_sum);
+        prog.register_op(51, 2, double);
+        assert_eq!(prog.run(), Some(42));
+        assert_eq!(prog.run(), None);
+        assert_eq!(prog.read(8), 42);
+        // a handler's writes are undone like those of built in opcodes
+        let mut prog = Program::new(vec![151, 21, 8, 1050, 8, 0, 99, 0, 0]);
+        prog.register_op(50, 2, print_sum);
+        prog.register_op(51, 2, double);
+        prog.record_history();
+        assert_eq!(prog.run(), Some(42));
+        assert!(prog.rewind_to(0));
+        assert_eq!((prog.head(), prog.read(8)), (0, 0));
+    }
+    #[test]
+    #[should_panic(expected = "bad op code (50) at (0)")]
+    fn unregistered_op() {
+        Program::new(vec![50, 0, 99]).run();
+    }
+    #[test]
+    #[should_panic(expected = "negative address (-1) at (0)")]
+    fn negative_position() {
+        Program::new(vec![4, -1, 99]).run();
+    }
+    #[test]
+    #[should_panic(expected = "negative address (-5) at (0)")]
+    fn negative_relative_read() {
+        Program::new(vec![204, -5, 99]).run();
+    }
+    #[test]
+    #[should_panic(expected = "negative address (-5) at (0)")]
+    fn negative_relative_write_paged() {
+        let code = [21101, 7, 0, -5, 99];
+        Program::with_memory(PagedMemory::new(&code)).run();
+    }
+    #[test]
+    #[should_panic(expected = "op code (7) is taken")]
+    fn register_builtin_op() {
+        fn nop(_: &mut Program, _: &[Param]) -> Option<i64> {
+            None
+        }
+        Program::new(vec![99]).register_op(7, 0, nop);
+    }
+    #[derive(Default)]
+    struct Tracer {
+        lines: Vec<String>,
+    }
+    impl Hook for Tracer {
+        fn instruction(&mut self, addr: usize, op: &Op) {
+            self.lines.push(format!("{}: {}", addr, op));
+        }
+        fn write(&mut self, addr: usize, old: i64, new: i64) {
+            self.lines.push(format!("[{}] {} -> {}", addr, old, new));
+        }
+        fn base(&mut self, old: usize, new: usize) {
+            self.lines.push(format!("rb {} -> {}", old, new));
+        }
+    }
+    #[test]
+    fn hooks() {
+        let mut prog = Program::new(vec![109, 10, 21101, 2, 3, 1, 4, 11, 99]);
+        let tracer = Arc::new(Mutex::new(Tracer::default()));
+        prog.add_hook(tracer.clone());
+        assert_eq!(prog.run(), Some(5));
+        assert_eq!(prog.run(), None);
+        assert_eq!(
+            tracer.lock().unwrap().lines,
+            vec![
+                "0: arb 10",
+                "rb 0 -> 10",
+                "2: add 2, 3, [rb+1]",
+                "[11] 0 -> 5",
+                "6: out [11]",
+                "8: halt",
+            ]
+        );
+        assert_eq!(prog.take_hooks().len(), 1);
+        prog.run();
+        assert_eq!(tracer.lock().unwrap().lines.len(), 6);
+    }
+    #[test]
+    fn hooks_compose() {
+        #[derive(Default)]
+        struct Count(usize);
+        impl Hook for Count {
+            fn instruction(&mut self, _addr: usize, _op: &Op) {
+                self.0 += 1;
+            }
+        }
+        fn send<T: Send>(_: &T) {}
+        let mut prog = Program::new(vec![109, 10, 21101, 2, 3, 1, 4, 11, 99]);
+        send(&prog);
+        let (tracer, count) = (
+            Arc::new(Mutex::new(Tracer::default())),
+            Arc::new(Mutex::new(Count::default())),
+        );
+        prog.add_hook(tracer.clone());
+        prog.add_hook(count.clone());
+        prog.add_hook(Count::default());
+        // a program with hooks can still move to another thread
+        send(&prog);
+        assert_eq!(prog.hooks(), 3);
+        assert_eq!(prog.clone().hooks(), 0);
+        assert_eq!(prog.run(), Some(5));
+        assert_eq!(prog.run(), None);
+        assert_eq!(tracer.lock().unwrap().lines.len(), 6);
+        assert_eq!(count.lock().unwrap().0, 4);
+    }
+    #[test]
+    fn profiling_hook() {
+        #[derive(Default)]
+        struct Profile(HashMap<usize, u64>);
+        impl Hook for Profile {
+            fn instruction(&mut self, addr: usize, _op: &Op) {
+                *self.0.entry(addr).or_default() += 1;
+            }
+        }
+        let code: Vec<i64> = fs::read_to_string("input/day9")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let mut prog = Program::new(code);
+        let profile = Arc::new(Mutex::new(Profile::default()));
+        prog.add_hook(profile.clone());
+        prog.run_input(Some(1));
+        assert_eq!(prog.run(), None);
+        // stepping the halted machine again isn't seen twice
+        assert_eq!(prog.run(), None);
+        // the halt is seen by the hook but not counted as a step
+        let seen: u64 = profile.lock().unwrap().0.values().sum();
+        assert_eq!(seen, prog.steps() + 1);
+    }
+    #[test]
+    fn batches() {
+        // reads a count, then outputs it and each number below it
+        let code = vec![3, 13, 4, 13, 1001, 13, -1, 13, 1005, 13, 2, 99, 99, 0];
+        let mut prog = Program::new(code);
+        let mut outputs = Vec::new();
+        assert_eq!(prog.run_for(5, &mut outputs), Stop::WaitInput);
+        assert_eq!(prog.steps(), 0);
+        prog.set_input(Some(5));
+        assert_eq!(prog.run_for(4, &mut outputs), Stop::Done);
+        assert_eq!((prog.steps(), &outputs[..]), (4, &[5][..]));
+        let stop = prog.run_until(&mut outputs, |_, outs| outs.len() == 2);
+        assert_eq!((stop, &outputs[..]), (Stop::Done, &[5, 4, 3][..]));
+        let stop = prog.run_until(&mut outputs, |prog, _| prog.read(13) == 1);
+        assert_eq!((stop, &outputs[..]), (Stop::Done, &[5, 4, 3, 2][..]));
+        assert_eq!(prog.run_until(&mut outputs, |_, _| false), Stop::Halt);
+        assert_eq!(outputs, vec![5, 4, 3, 2, 1]);
+        assert_eq!(prog.run_for(0, &mut outputs), Stop::Done);
+    }
+    #[test]
+    fn step_backwards() {
+        // read a value into 9, add 5 to it and output it
+        let mut prog = Program::new(vec![3, 9, 1001, 9, 5, 9, 4, 9, 99, 0]);
+        prog.record_history();
+        assert_eq!(prog.run_input(Some(3)), Some(8));
+        assert_eq!(prog.steps(), 3);
+        assert!(prog.step_back());
+        assert_eq!((prog.head(), prog.read(9)), (6, 8));
+        assert!(prog.reverse_continue(9));
+        assert_eq!((prog.head(), prog.read(9)), (2, 3));
+        assert!(prog.rewind_to(0));
+        assert_eq!((prog.head(), prog.read(9)), (0, 0));
+        assert!(!prog.step_back());
+        // the consumed input is restored, so the run can be replayed
+        assert_eq!(prog.run(), Some(8));
+        assert_eq!(prog.step(), Step::Halt);
+        assert!(!prog.reverse_continue(100));
+        assert_eq!(prog.steps(), 0);
+    }
+    #[test]
+    fn paged_memory() {
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101,
+            0, 99,
+        ];
+        let mut prog = Program::with_memory(PagedMemory::new(&quine));
+        let mut out = Vec::new();
+        while let Some(val) = prog.run() {
+            out.push(val);
+        }
+        assert_eq!(out, quine);
+        assert_eq!(prog.read(100), 16);
+        let addrs: Vec<usize> = prog.memory().addrs().collect();
+        assert_eq!(addrs, (0..256).collect::<Vec<_>>());
+
+        let mut memory = PagedMemory::default();
+        memory.store(1 << 40, 5);
+        memory.store(7, 0);
+        assert_eq!(memory.load(1 << 40), 5);
+        assert_eq!(memory.addrs().count(), 256);
+    }
+    #[test]
+    fn flat_memory_grows() {
+        let mut prog = Program::new(vec![1101, 2, 3, 10, 4, 10, 99]);
+        assert_eq!(prog.run(), Some(5));
+        assert_eq!(prog.memory().len(), 11);
+    }
+    // cargo test --release memory_backends -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn memory_backends_benchmark() {
+        fn load(path: &str) -> Vec<i64> {
+            fs::read_to_string(path)
+                .unwrap()
+                .trim()
+                .split(',')
+                .map(|s| s.parse().unwrap())
+                .collect()
+        }
+        fn time<M: Memory>(name: &str, mut prog: Program<M>, input: i64) {
+            let start = Instant::now();
+            prog.set_input(Some(input));
+            let mut outputs = 0;
+            while prog.run().is_some() {
+                outputs += 1;
+            }
+            println!(
+                "{:<12} {:>8} steps {:>5} outputs {:?}",
+                name,
+                prog.steps(),
+                outputs,
+                start.elapsed()
+            );
+        }
+        let day9 = load("input/day9");
+        time("day9 flat", Program::new(day9.clone()), 2);
+        time("day9 paged", Program::with_memory(PagedMemory::new(&day9)), 2);
+        let day13 = load("input/day13");
+        time("day13 flat", Program::new(day13.clone()), 0);
+        time("day13 paged", Program::with_memory(PagedMemory::new(&day13)), 0);
     }
 }
@@ -1,9 +1,12 @@
 #![allow(dead_code)]
+mod compiler;
+mod conformance;
+mod coverage;
 mod day1;
 mod day10;
 mod day11;
 mod day12;
-mod day13;
+pub mod day13;
 mod day15;
 mod day2;
 mod day3;
@@ -13,4 +16,11 @@ mod day6;
 mod day7;
 mod day8;
 mod day9;
-mod int_code;
+mod decompile;
+mod explore;
+mod fuzz;
+mod image;
+pub mod int_code;
+mod memory_map;
+mod ocr;
+mod transcript;
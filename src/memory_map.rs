@@ -0,0 +1,242 @@
+use super::int_code::{Hook, Memory, Op, Param, Program};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Code,
+    // addressed through the relative base
+    Stack,
+    // targets of input and sources of output
+    Io,
+    Data,
+    Unused,
+}
+impl Region {
+    fn name(self) -> &'static str {
+        match self {
+            Region::Code => "code",
+            Region::Stack => "stack",
+            Region::Io => "io",
+            Region::Data => "data",
+            Region::Unused => "unused",
+        }
+    }
+}
+/// Consecutive cells `start..end` of one region and the same access.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    pub start: usize,
+    pub end: usize,
+    pub region: Region,
+    pub read: bool,
+    pub written: bool,
+}
+/// Hook that records how each cell is used during a run.
+#[derive(Debug, Default)]
+pub struct MemoryMap {
+    image_len: usize,
+    base: usize,
+    executed: BTreeSet<usize>,
+    read: BTreeSet<usize>,
+    written: BTreeSet<usize>,
+    relative: BTreeSet<usize>,
+    io: BTreeSet<usize>,
+}
+impl Hook for MemoryMap {
+    fn instruction(&mut self, addr: usize, op: &Op) {
+        let (params, reads): (&[Param], usize) = match op {
+            Op::Add(p) | Op::Mul(p) | Op::Less(p) | Op::Equal(p) => (p, 2),
+            Op::JumpTrue(p) | Op::JumpFalse(p) => (p, 2),
+            Op::In(p) => (std::slice::from_ref(p), 0),
+            Op::Out(p) | Op::OffsetBase(p) => (std::slice::from_ref(p), 1),
+            Op::Halt => (&[], 0),
+            // which parameters an extension reads is up to its handler
+            Op::Ext(_, p) => (p, p.len()),
+        };
+        self.executed.extend(addr..addr + 1 + params.len());
+        for (i, &param) in params.iter().enumerate() {
+            let cell = match param {
+                Param::Pos(pos) => pos,
+                Param::Rel(rel) => {
+                    let cell = (self.base as i64 + rel) as usize;
+                    self.relative.insert(cell);
+                    cell
+                }
+                Param::Im(_) => continue,
+            };
+            if i < reads {
+                self.read.insert(cell);
+            }
+            if let Op::In(_) | Op::Out(_) = op {
+                self.io.insert(cell);
+            }
+        }
+    }
+    fn write(&mut self, addr: usize, _old: i64, _new: i64) {
+        self.written.insert(addr);
+    }
+    fn base(&mut self, _old: usize, new: usize) {
+        self.base = new;
+    }
+}
+impl MemoryMap {
+    /// Add a map to the hooks of `prog`, sized to its current image.
+    pub fn watch<M: Memory>(prog: &mut Program<M>) -> Arc<Mutex<MemoryMap>> {
+        let image_len = prog.memory().addrs().last().map_or(0, |a| a + 1);
+        let map = Arc::new(Mutex::new(MemoryMap {
+            image_len,
+            base: prog.base(),
+            ..MemoryMap::default()
+        }));
+        prog.add_hook(map.clone());
+        map
+    }
+    pub fn region(&self, addr: usize) -> Region {
+        if self.executed.contains(&addr) {
+            Region::Code
+        } else if self.relative.contains(&addr) {
+            Region::Stack
+        } else if self.io.contains(&addr) {
+            Region::Io
+        } else if self.read.contains(&addr) || self.written.contains(&addr) {
+            Region::Data
+        } else {
+            Region::Unused
+        }
+    }
+    /// The image and every cell touched beyond it, as runs of cells with the
+    /// same region and access.
+    pub fn ranges(&self) -> Vec<Range> {
+        let end = [&self.executed, &self.read, &self.written, &self.relative]
+            .iter()
+            .filter_map(|set| set.iter().next_back())
+            .map(|&last| last + 1)
+            .fold(self.image_len, usize::max);
+        let mut ranges: Vec<Range> = Vec::new();
+        for addr in 0..end {
+            let region = self.region(addr);
+            let read = self.read.contains(&addr);
+            let written = self.written.contains(&addr);
+            match ranges.last_mut() {
+                Some(last)
+                    if (last.region, last.read, last.written)
+                        == (region, read, written) =>
+                {
+                    last.end = addr + 1
+                }
+                _ => ranges.push(Range {
+                    start: addr,
+                    end: addr + 1,
+                    region,
+                    read,
+                    written,
+                }),
+            }
+        }
+        ranges
+    }
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for r in self.ranges() {
+            writeln!(
+                out,
+                "{:>6}..{:<6} {:>6}  {:<6} {}{}",
+                r.start,
+                r.end,
+                r.end - r.start,
+                r.region.name(),
+                if r.read { 'r' } else { '-' },
+                if r.written { 'w' } else { '-' },
+            )
+            .unwrap();
+        }
+        out
+    }
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("start,end,size,region,read,written\n");
+        for r in self.ranges() {
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                r.start,
+                r.end,
+                r.end - r.start,
+                r.region.name(),
+                r.read,
+                r.written
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryMap, Region};
+    use crate::int_code::Program;
+    use std::fs;
+    #[test]
+    fn classify_cells() {
+        let code = vec![
+            3, 17, // in [17]
+            1, 17, 18, 19, // add [17], [18], [19]
+            109, 30, // arb 30
+            22201, 0, 1, 2, // add [rb], [rb+1], [rb+2]
+            4, 19, // out [19]
+            99, 0, 0, 0, 5, 0,
+        ];
+        let mut prog = Program::new(code);
+        let map = MemoryMap::watch(&mut prog);
+        assert_eq!(prog.run_input(Some(2)), Some(7));
+        assert_eq!(prog.run(), None);
+        let map = map.lock().unwrap();
+        assert_eq!(
+            map.report(),
+            "     0..15         15  code   --
+    15..17          2  unused --
+    17..18          1  io     rw
+    18..19          1  data   r-
+    19..20          1  io     rw
+    20..30         10  unused --
+    30..32          2  stack  r-
+    32..33          1  stack  -w
+"
+        );
+        assert_eq!(
+            map.to_csv().lines().take(3).collect::<Vec<_>>(),
+            vec![
+                "start,end,size,region,read,written",
+                "0,15,15,code,false,false",
+                "15,17,2,unused,false,false",
+            ]
+        );
+    }
+    #[test]
+    fn day9_map() {
+        let code: Vec<i64> = fs::read_to_string("input/day9")
+            .unwrap()
+            .trim()
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let len = code.len();
+        let mut prog = Program::new(code);
+        prog.collect_coverage();
+        let map = MemoryMap::watch(&mut prog);
+        prog.run_input(Some(2));
+        let map = map.lock().unwrap();
+        let ranges = map.ranges();
+        assert_eq!(ranges[0].region, Region::Code);
+        // the boost program keeps its call stack past the end of the image
+        assert!(ranges
+            .iter()
+            .any(|r| r.region == Region::Stack && r.start >= len));
+        let coverage = prog.coverage().unwrap();
+        assert!(coverage
+            .executed()
+            .all(|addr| map.region(addr) == Region::Code));
+    }
+}
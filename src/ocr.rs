@@ -0,0 +1,204 @@
+//! Reading the block letters that puzzles draw, in the 6-row font of day 8
+//! and day 11 and the 10-row font of 2018's day 10.
+// each block of letters is drawn side by side
+const SMALL: [(&str, &str); 2] = [
+    (
+        "ABCEFGHIJ",
+        "\
+         .##..###...##..####.####..##..#..#.###...##\n\
+         #..#.#..#.#..#.#....#....#..#.#..#..#.....#\n\
+         #..#.###..#....###..###..#....####..#.....#\n\
+         ####.#..#.#....#....#....#.##.#..#..#.....#\n\
+         #..#.#..#.#..#.#....#....#..#.#..#..#..#..#\n\
+         #..#.###...##..####.#.....###.#..#.###..##.",
+    ),
+    (
+        "KLOPRSUYZ",
+        "\
+         #..#.#.....##..###..###...###.#..#.#...#.####\n\
+         #.#..#....#..#.#..#.#..#.#....#..#.#...#....#\n\
+         ##...#....#..#.#..#.#..#.#....#..#..#.#....#.\n\
+         #.#..#....#..#.###..###...##..#..#...#....#..\n\
+         #.#..#....#..#.#....#.#.....#.#..#...#...#...\n\
+         #..#.####..##..#....#..#.###...##....#...####",
+    ),
+];
+const LARGE: [(&str, &str); 3] = [
+    (
+        "ABCEF",
+        "\
+         ..##....#####....####...######..######\n\
+         .#..#...#....#..#....#..#.......#.....\n\
+         #....#..#....#..#.......#.......#.....\n\
+         #....#..#....#..#.......#.......#.....\n\
+         #....#..#####...#.......#####...#####.\n\
+         ######..#....#..#.......#.......#.....\n\
+         #....#..#....#..#.......#.......#.....\n\
+         #....#..#....#..#.......#.......#.....\n\
+         #....#..#....#..#....#..#.......#.....\n\
+         #....#..#####....####...######..#.....",
+    ),
+    (
+        "GHJKL",
+        "\
+         .####...#....#.....###..#....#..#.....\n\
+         #....#..#....#......#...#...#...#.....\n\
+         #.......#....#......#...#..#....#.....\n\
+         #.......#....#......#...#.#.....#.....\n\
+         #.......######......#...##......#.....\n\
+         #..###..#....#......#...##......#.....\n\
+         #....#..#....#......#...#.#.....#.....\n\
+         #....#..#....#..#...#...#..#....#.....\n\
+         #...##..#....#..#...#...#...#...#.....\n\
+         .###.#..#....#...###....#....#..######",
+    ),
+    (
+        "NPRXZ",
+        "\
+         #....#..#####...#####...#....#..######\n\
+         ##...#..#....#..#....#..#....#.......#\n\
+         ##...#..#....#..#....#...#..#........#\n\
+         #.#..#..#....#..#....#...#..#.......#.\n\
+         #.#..#..#####...#####.....##.......#..\n\
+         #..#.#..#.......#..#......##......#...\n\
+         #..#.#..#.......#...#....#..#....#....\n\
+         #...##..#.......#...#....#..#...#.....\n\
+         #...##..#.......#....#..#....#..#.....\n\
+         #....#..#.......#....#..#....#..######",
+    ),
+];
+
+/// Grid of lit cells from text rows, `#` being lit.
+pub fn grid(text: &str) -> Vec<Vec<bool>> {
+    text.lines().map(|line| line.chars().map(|c| c == '#').collect()).collect()
+}
+fn draw(rows: &[Vec<bool>]) -> String {
+    let rows: Vec<String> = rows
+        .iter()
+        .map(|row| row.iter().map(|&lit| if lit { '#' } else { '.' }).collect())
+        .collect();
+    rows.join("\n")
+}
+// Cut out the runs of lit columns, with the column each starts at.
+fn split(rows: &[Vec<bool>]) -> Vec<(usize, Vec<Vec<bool>>)> {
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let lit = |x: usize| rows.iter().any(|row| row.get(x) == Some(&true));
+    let mut glyphs = Vec::new();
+    let mut x = 0;
+    while x < width {
+        if !lit(x) {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        while x < width && lit(x) {
+            x += 1;
+        }
+        let glyph = rows
+            .iter()
+            .map(|row| (start..x).map(|i| row.get(i) == Some(&true)).collect())
+            .collect();
+        glyphs.push((start, glyph));
+    }
+    glyphs
+}
+fn columns(rows: &[Vec<bool>], from: usize, to: usize) -> Vec<Vec<bool>> {
+    rows.iter().map(|row| row[from..to].to_vec()).collect()
+}
+/// Read the letters in `rows`, ignoring blank margins. Letters are split
+/// at blank columns, or where a known letter ends if two touch. Unknown
+/// letters make an error that shows the text read, with `?` for each
+/// unknown, followed by a drawing of every unknown letter.
+pub fn read(rows: &[Vec<bool>]) -> Result<String, String> {
+    let lit_row = |row: &Vec<bool>| row.iter().any(|&lit| lit);
+    let top = rows.iter().position(lit_row).unwrap_or(0);
+    let bottom = rows.iter().rposition(lit_row).map_or(0, |r| r + 1);
+    let rows = &rows[top..bottom];
+    let font: &[(&str, &str)] = match rows.len() {
+        0 => return Ok(String::new()),
+        6 => &SMALL,
+        10 => &LARGE,
+        height => return Err(format!("no font is {} rows high", height)),
+    };
+    let known: Vec<(char, Vec<Vec<bool>>)> = font
+        .iter()
+        .flat_map(|&(letters, drawing)| {
+            let glyphs = split(&grid(drawing)).into_iter().map(|(_, g)| g);
+            letters.chars().zip(glyphs).collect::<Vec<_>>()
+        })
+        .collect();
+    let mut text = String::new();
+    let mut unknown = Vec::new();
+    for (start, run) in split(rows) {
+        // letters that fill their whole cell, like Y, can touch the next
+        // one, so take known letters off the front of the run
+        let width = run[0].len();
+        let mut at = 0;
+        while at < width {
+            let rest = columns(&run, at, width);
+            let letter = known
+                .iter()
+                .filter(|(_, glyph)| glyph[0].len() <= width - at)
+                .filter(|(_, glyph)| {
+                    columns(&rest, 0, glyph[0].len()) == *glyph
+                })
+                .max_by_key(|(_, glyph)| glyph[0].len());
+            match letter {
+                Some((letter, glyph)) => {
+                    text.push(*letter);
+                    at += glyph[0].len();
+                }
+                None => {
+                    text.push('?');
+                    unknown.push(format!(
+                        "unknown letter at column {}:\n{}",
+                        start + at,
+                        draw(&rest)
+                    ));
+                    break;
+                }
+            }
+        }
+    }
+    if unknown.is_empty() {
+        Ok(text)
+    } else {
+        Err(format!("read {}\n{}", text, unknown.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grid, read, LARGE, SMALL};
+    #[test]
+    fn whole_fonts() {
+        for &(letters, drawing) in SMALL.iter().chain(&LARGE) {
+            assert_eq!(read(&grid(drawing)), Ok(letters.to_string()));
+        }
+    }
+    #[test]
+    fn margins_and_errors() {
+        let text = "\
+            ..........\n\
+            .#..#.###.\n\
+            .#..#..#..\n\
+            .####..#..\n\
+            .#..#..#..\n\
+            .#..#..#..\n\
+            .#..#.###.\n\
+            ..........";
+        assert_eq!(read(&grid(text)), Ok("HI".to_string()));
+        let text = "#..#.#\n#..#.#\n####.#\n#..#..\n#..#.#\n#..#.#";
+        assert_eq!(
+            read(&grid(text)),
+            Err("read H?\nunknown letter at column 5:\n#\n#\n#\n.\n#\n#"
+                .to_string())
+        );
+        // Y fills its cell and touches the A
+        let text =
+            "#...#.##.\n#...##..#\n.#.#.#..#\n..#..####\n..#..#..#\n..#..#..#";
+        assert_eq!(read(&grid(text)), Ok("YA".to_string()));
+        assert_eq!(read(&grid("#\n#")), Err("no font is 2 rows high".into()));
+        assert_eq!(read(&grid("...\n...")), Ok(String::new()));
+    }
+}
@@ -0,0 +1,282 @@
+//! Golden transcripts of intcode I/O. A session run through a `Recorder`
+//! keeps every consumed input and produced output, and `assert_golden`
+//! compares that against a text file such as
+//!
+//! ```text
+//! # day13, first frames
+//! out -1 0 0
+//! in 1
+//! ```
+//!
+//! where each line is one input or up to `width` outputs.
+use super::int_code::{Memory, Program, Step};
+use std::fmt::Write;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    In(i64),
+    Out(i64),
+}
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transcript {
+    pub events: Vec<Event>,
+}
+impl Transcript {
+    pub fn parse(text: &str) -> Result<Transcript, String> {
+        let mut events = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || format!("line {}: can't read '{}'", line_no + 1, line);
+            let mut words = line.split_whitespace();
+            let kind = words.next().unwrap();
+            let vals = words
+                .map(|w| w.parse::<i64>().map_err(|_| bad()))
+                .collect::<Result<Vec<_>, _>>()?;
+            match (kind, vals.as_slice()) {
+                ("in", &[val]) => events.push(Event::In(val)),
+                ("out", vals) if !vals.is_empty() => {
+                    events.extend(vals.iter().map(|&v| Event::Out(v)))
+                }
+                _ => return Err(bad()),
+            }
+        }
+        Ok(Transcript { events })
+    }
+    /// Render with up to `width` consecutive outputs per line, e.g. 3 to
+    /// keep day13's tile triples together.
+    pub fn to_text(&self, width: usize) -> String {
+        let mut text = String::new();
+        let mut outs = 0;
+        for event in &self.events {
+            match event {
+                Event::In(val) => {
+                    if outs > 0 {
+                        text.push('\n');
+                        outs = 0;
+                    }
+                    writeln!(text, "in {}", val).unwrap();
+                }
+                Event::Out(val) => {
+                    if outs == 0 {
+                        text.push_str("out");
+                    }
+                    write!(text, " {}", val).unwrap();
+                    outs += 1;
+                    if outs == width {
+                        text.push('\n');
+                        outs = 0;
+                    }
+                }
+            }
+        }
+        if outs > 0 {
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// A program that records its I/O. It offers the same `run`, `set_input`
+/// and `run_input` as `Program`, so a driver can switch over without other
+/// changes.
+pub struct Recorder<M = Vec<i64>> {
+    prog: Program<M>,
+    transcript: Transcript,
+}
+impl<M: Memory> Recorder<M> {
+    pub fn new(prog: Program<M>) -> Self {
+        Recorder { prog, transcript: Transcript::default() }
+    }
+    pub fn run_input(&mut self, input: Option<i64>) -> Option<i64> {
+        self.prog.set_input(input);
+        self.run()
+    }
+    pub fn set_input(&mut self, input: Option<i64>) {
+        self.prog.set_input(input);
+    }
+    pub fn run(&mut self) -> Option<i64> {
+        loop {
+            // inputs are logged when read, not when offered
+            let pending = self.prog.input();
+            let step = self.prog.step();
+            if let (Some(val), None) = (pending, self.prog.input()) {
+                self.transcript.events.push(Event::In(val));
+            }
+            match step {
+                Step::Ran => {}
+                Step::Output(out) => {
+                    self.transcript.events.push(Event::Out(out));
+                    return Some(out);
+                }
+                Step::WaitInput | Step::Halt => return None,
+            }
+        }
+    }
+    pub fn program(&mut self) -> &mut Program<M> {
+        &mut self.prog
+    }
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+}
+
+/// Line diff of two texts as the hunk between their common start and end,
+/// with a few lines of context. None if they are equal.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    const CONTEXT: usize = 3;
+    const MAX_LINES: usize = 20;
+    let old: Vec<_> = expected.lines().collect();
+    let new: Vec<_> = actual.lines().collect();
+    if old == new {
+        return None;
+    }
+    let start = old.iter().zip(&new).take_while(|(o, n)| o == n).count();
+    let end = old[start..]
+        .iter()
+        .rev()
+        .zip(new[start..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let (old_end, new_end) = (old.len() - end, new.len() - end);
+    let from = start.saturating_sub(CONTEXT);
+    let mut out = format!(
+        "@@ expected lines {}..{}, actual lines {}..{} @@\n",
+        start + 1,
+        old_end + 1,
+        start + 1,
+        new_end + 1
+    );
+    for line in &old[from..start] {
+        writeln!(out, "  {}", line).unwrap();
+    }
+    for (sign, lines) in
+        [('-', &old[start..old_end]), ('+', &new[start..new_end])]
+    {
+        for line in lines.iter().take(MAX_LINES) {
+            writeln!(out, "{} {}", sign, line).unwrap();
+        }
+        if lines.len() > MAX_LINES {
+            writeln!(out, "{} ... {} more", sign, lines.len() - MAX_LINES)
+                .unwrap();
+        }
+    }
+    for line in old[old_end..].iter().take(CONTEXT) {
+        writeln!(out, "  {}", line).unwrap();
+    }
+    Some(out)
+}
+
+/// Compare `transcript` with the golden file at `path`, panicking with a
+/// diff if they differ or if the file is missing. Setting `UPDATE_GOLDEN`
+/// writes the file instead, to record a new golden file or accept an
+/// intended change.
+pub fn assert_golden(path: &str, transcript: &Transcript, width: usize) {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    if let Err(e) = check_golden(path, transcript, width, update) {
+        panic!("{}", e);
+    }
+}
+fn check_golden(
+    path: &str,
+    transcript: &Transcript,
+    width: usize,
+    update: bool,
+) -> Result<(), String> {
+    let actual = transcript.to_text(width);
+    if update {
+        return fs::write(path, &actual)
+            .map_err(|e| format!("can't write {}: {}", path, e));
+    }
+    let expected = fs::read_to_string(path).map_err(|e| {
+        format!(
+            "can't read golden file {}: {} (set UPDATE_GOLDEN to create it)",
+            path, e
+        )
+    })?;
+    let golden =
+        Transcript::parse(&expected).map_err(|e| format!("{}: {}", path, e))?;
+    if golden != *transcript {
+        let diff = diff(&golden.to_text(width), &actual).unwrap();
+        return Err(format!(
+            "transcript differs from {} (set UPDATE_GOLDEN to accept)\n{}",
+            path, diff
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_golden, diff, Event, Recorder, Transcript};
+    use crate::compiler::compile;
+    use crate::int_code::Program;
+    #[test]
+    fn record_and_render() {
+        let source = "
+            fn main() {
+                output(7);
+                let n = input();
+                while n > 0 {
+                    output(n);
+                    output(n * n);
+                    n = n - 1;
+                }
+            }
+        ";
+        let mut rec = Recorder::new(Program::new(compile(source).unwrap()));
+        // the input isn't read until after the first output
+        assert_eq!(rec.run_input(Some(2)), Some(7));
+        while rec.run().is_some() {}
+        let text = rec.transcript().to_text(2);
+        assert_eq!(text, "out 7\nin 2\nout 2 4\nout 1 1\n");
+        assert_eq!(Transcript::parse(&text).as_ref(), Ok(rec.transcript()));
+        assert_eq!(
+            Transcript::parse("# comment\n\nout 1 2 3\nin -1").unwrap().events,
+            vec![Event::Out(1), Event::Out(2), Event::Out(3), Event::In(-1)]
+        );
+        assert_eq!(
+            Transcript::parse("in 1 2"),
+            Err("line 1: can't read 'in 1 2'".to_string())
+        );
+    }
+    #[test]
+    fn readable_diff() {
+        let old = "in 1\nout 1\nout 2\nout 3\nout 4\nout 5\nout 6\nout 7\n";
+        let new = "in 1\nout 1\nout 2\nout 3\nout 4\nout 9\nout 6\nout 7\n";
+        assert_eq!(diff(old, old), None);
+        assert_eq!(
+            diff(old, new).unwrap(),
+            "@@ expected lines 6..7, actual lines 6..7 @@\n  out 2\n  out 3\n  \
+             out 4\n- out 5\n+ out 9\n  out 6\n  out 7\n"
+        );
+        assert_eq!(
+            diff("out 1\n", "out 1\nin 2\n").unwrap(),
+            "@@ expected lines 2..2, actual lines 2..3 @@\n  out 1\n+ in 2\n"
+        );
+    }
+    #[test]
+    fn golden_files() {
+        let transcript =
+            Transcript { events: vec![Event::In(1), Event::Out(2)] };
+        let path = std::env::temp_dir()
+            .join(format!("golden_files_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let missing = check_golden(path, &transcript, 3, false);
+        let written = check_golden(path, &transcript, 3, true);
+        let same = check_golden(path, &transcript, 3, false);
+        let other = Transcript { events: vec![Event::In(1)] };
+        let changed = check_golden(path, &other, 3, false);
+        // clean up before asserting
+        let _ = std::fs::remove_file(path);
+        // a missing file fails rather than being recorded
+        let err = missing.unwrap_err();
+        assert!(err.starts_with("can't read golden file"), "{}", err);
+        assert_eq!((written, same), (Ok(()), Ok(())));
+        let err = changed.unwrap_err();
+        assert!(err.contains("- out 2"), "{}", err);
+    }
+}